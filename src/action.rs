@@ -1,10 +1,22 @@
 use std::error::Error;
 use std::fmt::Debug;
-use std::process::{Command, Stdio};
-use rnotifylib::message::MessageDetail;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use chrono::Local;
+use rnotifylib::message::formatted_detail::FormattedString;
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
 use serde::{Serialize, Deserialize};
-use crate::job_result::JobResult;
-use crate::program_output::ProgramOutput;
+use crate::config::JobDefinitionId;
+use crate::duration_format::format_duration;
+use crate::host;
+use crate::job_result::{JobOutcome, JobResult};
+use crate::job_state::{JobState, ResultKind};
+use crate::metrics::{Metrics, ThresholdLevel};
+use crate::notify_definition::NotifyDefinition;
+use crate::program_output;
+use crate::program_output::{ProgramOutput, RunMetadata};
+use crate::redact::Redactor;
+use crate::template::{TemplateContext, TemplateVariable};
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum ProgramOutputFormat {
@@ -17,6 +29,8 @@ pub enum ProgramOutputFormat {
     ///     component2:failure:reason for failure
     ///     component3:success
     List,
+    /// Shows a unified diff of the program's stdout against the stdout of its previous successful run.
+    Diff,
 }
 
 impl Default for ProgramOutputFormat {
@@ -25,21 +39,90 @@ impl Default for ProgramOutputFormat {
     }
 }
 
-pub async fn execute(cmd: &str, format: &ProgramOutputFormat) -> JobResult {
-    match run_program(cmd) {
+/// Runs the job, and creates the outcome to report.
+/// started: the unix time the run was started at, and next_run: when the job is next scheduled to run.
+pub async fn execute(job_id: &JobDefinitionId, cmd: &str, notify_definition: &NotifyDefinition,
+                     redactor: &Redactor, previous: &JobState, started: u64, next_run: Option<u64>) -> JobOutcome {
+    let start = Instant::now();
+    let create_metadata = |duration: Duration| {
+        let mut metadata = RunMetadata::new(host::get_hostname(), cmd.to_owned(), started, duration.as_secs(),
+                                            previous.get_consecutive_unsuccessful_runs() + 1, previous.get_last_success(), next_run);
+        metadata.redact(redactor);
+        metadata
+    };
+    let result = run_program(cmd);
+    let duration = start.elapsed();
+    let now = Local::now().timestamp() as u64;
+    match result {
         Ok(mut output) => {
+            let metadata = create_metadata(duration).with_exit(output.get_exit_code(), output.get_signal());
+            output.redact(redactor);
             let success = output.is_success();
-            output.trim_to(500);
-            let detail = output.to_detail(format);
-            match success {
-                true => JobResult::Ok(detail),
-                false => JobResult::Failed(detail),
+            // Only jobs with thresholds report metrics, so that other output that looks like a metric isn't shown or stored.
+            let metrics = match notify_definition.get_thresholds().is_empty() {
+                true => Metrics::new(),
+                false => output.get_metrics(),
+            };
+            let threshold_level = notify_definition.evaluate_thresholds(&metrics);
+
+            let previous_stdout = previous.get_last_stdout();
+            let stdout = Some(output.get_stdout().to_owned())
+                .filter(|_| success && notify_definition.tracks_output());
+            let output_changed = match (&stdout, previous_stdout) {
+                (Some(stdout), Some(previous_stdout)) => stdout != previous_stdout,
+                _ => false,
+            };
+
+            let kind = match (success, threshold_level) {
+                (false, _) | (true, ThresholdLevel::Critical) => ResultKind::Failed,
+                (true, ThresholdLevel::Warning) => ResultKind::Warning,
+                (true, ThresholdLevel::Normal) => ResultKind::Ok,
+            };
+
+            let mut context = create_template_context(job_id, previous, kind, duration);
+            context.set(TemplateVariable::ExitCode, output.get_exit_code());
+            context.set(TemplateVariable::StdoutFirstLine, output.get_first_line());
+            let title = notify_definition.render_title(&context);
+            output.set_summary(notify_definition.render_summary(&context));
+            output.set_metadata(metadata, now);
+
+            let mut builder = output.to_detail_builder(notify_definition.get_output_format(), previous_stdout);
+            if !metrics.is_empty() {
+                builder = program_output::append_metrics_section(builder, &metrics, notify_definition, previous, now);
             }
+            let result = JobResult::new(kind, builder.build());
+            JobOutcome::new(result, title, metrics, stdout, output_changed)
+                .with_context(context)
+                .with_duration(duration.as_secs())
         },
-        Err(e) => JobResult::Invalid(MessageDetail::Raw(format!("Failed to run command: '{}'\nError: {e}", &cmd)))
+        Err(e) => {
+            let context = create_template_context(job_id, previous, ResultKind::Invalid, duration);
+            let title = notify_definition.render_title(&context);
+            let mut raw = redactor.redact(&format!("Failed to run command: '{}'\nError: {e}", &cmd));
+            if let Some(summary) = notify_definition.render_summary(&context) {
+                raw = format!("{summary}\n{raw}");
+            }
+            let detail = program_output::append_metadata_section(MessageDetailBuilder::with_raw(raw.clone()), &create_metadata(duration), now)
+                .text(vec![FormattedString::plain(raw)])
+                .build();
+            JobOutcome::new(JobResult::Invalid(detail), title, Metrics::new(), None, false)
+                .with_context(context)
+                .with_duration(duration.as_secs())
+        }
     }
 }
 
+/// Creates a template context with the variables that are known regardless of the program's output.
+pub fn create_template_context(job_id: &JobDefinitionId, previous: &JobState, kind: ResultKind, duration: Duration) -> TemplateContext {
+    let mut context = TemplateContext::new();
+    context.set(TemplateVariable::JobId, job_id);
+    context.set(TemplateVariable::Hostname, host::get_hostname());
+    context.set(TemplateVariable::Duration, format_duration(duration.as_secs()));
+    context.set(TemplateVariable::Attempt, previous.get_consecutive_unsuccessful_runs() + 1);
+    context.set(TemplateVariable::Result, kind.type_str());
+    context
+}
+
 #[cfg(target_family = "windows")]
 fn make_command() -> Command {
     let mut cmd = Command::new("cmd");
@@ -68,5 +151,18 @@ fn run_program(job: &str) -> Result<ProgramOutput, Box<dyn Error>> {
     let std_out = String::from_utf8_lossy(&output.stdout);
     let std_err = String::from_utf8_lossy(&output.stderr);
 
-    Ok(ProgramOutput::new(std_out.into(), std_err.into(), output.status.code().unwrap_or(-1)))
+    Ok(ProgramOutput::new(program_output::sanitize(&std_out), program_output::sanitize(&std_err),
+                          output.status.code().unwrap_or(-1))
+        .with_signal(get_signal(&output.status)))
+}
+
+#[cfg(target_family = "unix")]
+fn get_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(target_family = "windows")]
+fn get_signal(_status: &ExitStatus) -> Option<i32> {
+    None
 }
\ No newline at end of file
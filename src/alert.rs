@@ -0,0 +1,225 @@
+use serde::{Serialize, Deserialize};
+use crate::frequency::FixedPeriodInner;
+use crate::job_state::{JobState, ResultKind};
+
+/// An ongoing problem with a job, which has been (or would have been) notified.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// When the first unsuccessful run of the problem started.
+    since: u64,
+    /// The result that caused the alert, or that it last changed to.
+    kind: ResultKind,
+    /// When the alert was last notified.
+    #[serde(default)]
+    last_notified: u64,
+}
+
+impl Alert {
+    pub fn get_since(&self) -> u64 {
+        self.since
+    }
+
+    pub fn get_kind(&self) -> ResultKind {
+        self.kind
+    }
+
+    pub fn get_last_notified(&self) -> u64 {
+        self.last_notified
+    }
+}
+
+/// What a run of a job means for its alert.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertEvent {
+    /// The run was successful, and there was no alert.
+    Ok,
+    /// The run was unsuccessful, but not enough consecutive runs have failed to raise an alert.
+    Suppressed,
+    /// An alert has been raised.
+    Raised,
+    /// The alert is ongoing, but its result changed, e.g. from a warning to a failure.
+    Changed,
+    /// The alert is ongoing, with the same result.
+    Ongoing,
+    /// The alert is ongoing, and it has been long enough since it was notified to remind about it.
+    Reminder,
+    /// The run was successful, but not enough consecutive runs have succeeded to resolve the alert.
+    RecoveryPending,
+    /// The alert has been resolved.
+    Recovered { since: u64 },
+    /// The job has started alternating between success and failure too often.
+    FlappingStarted,
+    /// The job is still flapping, so individual changes are not notified.
+    Flapping,
+    /// The job has stopped flapping.
+    FlappingStopped,
+}
+
+/// The state of the job's alert after a run, and the event that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertUpdate {
+    alert: Option<Alert>,
+    flapping: bool,
+    event: AlertEvent,
+}
+
+impl AlertUpdate {
+    pub fn get_alert(&self) -> Option<&Alert> {
+        self.alert.as_ref()
+    }
+
+    pub fn is_flapping(&self) -> bool {
+        self.flapping
+    }
+
+    pub fn get_event(&self) -> &AlertEvent {
+        &self.event
+    }
+}
+
+/// When a job is considered to be flapping.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FlapDetection {
+    /// The number of changes between success and failure within the window, above which the job is flapping.
+    max_transitions: u32,
+    /// The job stops flapping once it has no changes within the window.
+    window: FixedPeriodInner,
+}
+
+impl FlapDetection {
+    pub fn new(max_transitions: u32, window: FixedPeriodInner) -> Self {
+        Self {
+            max_transitions,
+            window,
+        }
+    }
+}
+
+/// How many consecutive runs are needed to raise and resolve an alert,
+/// how often to remind about an ongoing alert, and when the job is flapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertPolicy {
+    failure_threshold: u32,
+    recovery_threshold: u32,
+    /// Seconds between reminders, or None to not remind.
+    renotify_interval: Option<u64>,
+    flap_detection: Option<FlapDetection>,
+}
+
+impl AlertPolicy {
+    pub fn new(failure_threshold: u32, recovery_threshold: u32, renotify_interval: Option<u64>,
+               flap_detection: Option<FlapDetection>) -> Self {
+        Self {
+            failure_threshold,
+            recovery_threshold,
+            renotify_interval,
+            flap_detection,
+        }
+    }
+}
+
+/// Works out what a run with the given result means for the job's alert.
+pub fn evaluate(state: &JobState, kind: ResultKind, started: u64, policy: &AlertPolicy) -> AlertUpdate {
+    let (alert, event) = evaluate_alert(state, kind, started, policy);
+
+    let flap_detection = match &policy.flap_detection {
+        Some(flap_detection) => flap_detection,
+        None => return AlertUpdate { alert, flapping: false, event },
+    };
+    let window_start = started.saturating_sub(flap_detection.window.as_seconds());
+    let transitions = state.count_transitions_since(window_start, kind);
+
+    let (flapping, event) = match (state.is_flapping(), transitions) {
+        (false, t) if t > flap_detection.max_transitions => (true, AlertEvent::FlappingStarted),
+        (false, _) => (false, event),
+        (true, 0) => (false, AlertEvent::FlappingStopped),
+        (true, _) => (true, AlertEvent::Flapping),
+    };
+    AlertUpdate { alert, flapping, event }
+}
+
+fn evaluate_alert(state: &JobState, kind: ResultKind, started: u64, policy: &AlertPolicy) -> (Option<Alert>, AlertEvent) {
+    match (state.get_alert().cloned(), kind.is_ok()) {
+        (None, true) => (None, AlertEvent::Ok),
+        (None, false) => {
+            let failures = state.get_consecutive_unsuccessful_runs() + 1;
+            if failures < policy.failure_threshold {
+                return (None, AlertEvent::Suppressed);
+            }
+            let since = state.get_unsuccessful_since().unwrap_or(started);
+            (Some(Alert { since, kind, last_notified: started }), AlertEvent::Raised)
+        }
+        (Some(alert), false) => {
+            if alert.kind != kind {
+                return (Some(Alert { kind, last_notified: started, ..alert }), AlertEvent::Changed);
+            }
+            match policy.renotify_interval {
+                Some(interval) if started >= alert.last_notified + interval => {
+                    (Some(Alert { last_notified: started, ..alert }), AlertEvent::Reminder)
+                }
+                _ => (Some(alert), AlertEvent::Ongoing),
+            }
+        }
+        (Some(alert), true) => {
+            let successes = state.get_consecutive_successful_runs() + 1;
+            if successes < policy.recovery_threshold {
+                return (Some(alert), AlertEvent::RecoveryPending);
+            }
+            (None, AlertEvent::Recovered { since: alert.since })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::JobDefinitionId;
+    use crate::job_state::{JobStates, RunRecord};
+    use crate::metrics::Metrics;
+    use super::*;
+
+    fn run(states: &mut JobStates, id: &JobDefinitionId, policy: &AlertPolicy, kind: ResultKind, started: u64) -> AlertEvent {
+        let update = evaluate(&states.get_or_default(id), kind, started, policy);
+        states.set_alert(id.clone(), update.get_alert().cloned(), update.is_flapping());
+        states.record(id.clone(), RunRecord::new(started, kind, Metrics::new()));
+        update.event
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let id = JobDefinitionId::try_new("ping".to_owned()).unwrap();
+        let policy = AlertPolicy::new(3, 2, Some(100), None);
+        let mut states = JobStates::default();
+        let mut step = |kind: ResultKind, started: u64| run(&mut states, &id, &policy, kind, started);
+
+        assert_eq!(AlertEvent::Ok, step(ResultKind::Ok, 0));
+        assert_eq!(AlertEvent::Suppressed, step(ResultKind::Failed, 10));
+        assert_eq!(AlertEvent::Suppressed, step(ResultKind::Failed, 20));
+        assert_eq!(AlertEvent::Raised, step(ResultKind::Failed, 30));
+        assert_eq!(AlertEvent::Ongoing, step(ResultKind::Failed, 40));
+        assert_eq!(AlertEvent::Changed, step(ResultKind::Invalid, 50));
+        assert_eq!(AlertEvent::Ongoing, step(ResultKind::Invalid, 100));
+        assert_eq!(AlertEvent::Reminder, step(ResultKind::Invalid, 150));
+        assert_eq!(AlertEvent::Ongoing, step(ResultKind::Invalid, 200));
+        assert_eq!(AlertEvent::RecoveryPending, step(ResultKind::Ok, 260));
+        assert_eq!(AlertEvent::Recovered { since: 10 }, step(ResultKind::Ok, 270));
+        assert_eq!(AlertEvent::Ok, step(ResultKind::Ok, 280));
+    }
+
+    #[test]
+    fn test_flapping() {
+        let id = JobDefinitionId::try_new("ping".to_owned()).unwrap();
+        let flap_detection = FlapDetection::new(2, FixedPeriodInner::new(0, 0, 100));
+        let policy = AlertPolicy::new(1, 1, None, Some(flap_detection));
+        let mut states = JobStates::default();
+        let mut step = |kind: ResultKind, started: u64| run(&mut states, &id, &policy, kind, started);
+
+        assert_eq!(AlertEvent::Ok, step(ResultKind::Ok, 0));
+        assert_eq!(AlertEvent::Raised, step(ResultKind::Failed, 10));
+        assert_eq!(AlertEvent::Recovered { since: 10 }, step(ResultKind::Ok, 20));
+        assert_eq!(AlertEvent::FlappingStarted, step(ResultKind::Failed, 30));
+        assert_eq!(AlertEvent::Flapping, step(ResultKind::Ok, 40));
+        assert_eq!(AlertEvent::Flapping, step(ResultKind::Ok, 100));
+        assert_eq!(AlertEvent::FlappingStopped, step(ResultKind::Ok, 150));
+        assert_eq!(AlertEvent::Ok, step(ResultKind::Ok, 160));
+    }
+}
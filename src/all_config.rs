@@ -1,12 +1,28 @@
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use getopts::Matches;
-use crate::{RNOTIFYD_CONFIG_ARG,  RNOTIFY_CONFIG_ARG, RNOTIFY_RUN_LOG_ARG};
+use rnotifydlib::config::JobDefinitionId;
+use rnotifydlib::notify_definition::DEFAULT_ROUTE;
+use rnotifydlib::redact::Redactor;
+use crate::{RNOTIFYD_CONFIG_ARG, RNOTIFYD_DEFERRED_ARG, RNOTIFYD_DIGEST_ARG, RNOTIFYD_OUTBOX_ARG, RNOTIFYD_SILENCES_ARG, RNOTIFYD_SOCKET_ARG, RNOTIFYD_STATE_ARG, DEFAULT_SOCKET, RNOTIFY_CONFIG_ARG, RNOTIFY_RUN_LOG_ARG};
 
 pub struct AllConfig {
-    rnotify: rnotifylib::config::Config,
+    /// The rnotify configs that messages can be sent through, by route name, including the default.
+    routes: HashMap<String, rnotifylib::config::Config>,
     job_config: rnotifydlib::config::Config,
+    /// The redactor of each job, built when the config is loaded, so that secret environment variables are read once.
+    redactors: HashMap<JobDefinitionId, Arc<Redactor>>,
+    rnotify_config_path: PathBuf,
+    rnotifyd_config_path: PathBuf,
     run_log: PathBuf,
+    state: PathBuf,
+    silences: PathBuf,
+    digest: PathBuf,
+    deferred: PathBuf,
+    outbox: PathBuf,
+    socket: PathBuf,
 }
 
 impl AllConfig {
@@ -14,13 +30,55 @@ impl AllConfig {
         &self.job_config
     }
 
-    pub fn get_rnotify_config(&self) -> &rnotifylib::config::Config {
-        &self.rnotify
+    pub fn get_routes(&self) -> &HashMap<String, rnotifylib::config::Config> {
+        &self.routes
+    }
+
+    /// Gets the redactor combining the global rules and the rules of the given job.
+    pub fn get_redactor(&self, id: &JobDefinitionId) -> Arc<Redactor> {
+        self.redactors.get(id).cloned().unwrap_or_default()
     }
 
     pub fn get_run_log_path(&self) -> &PathBuf {
         &self.run_log
     }
+
+    pub fn get_state_path(&self) -> &PathBuf {
+        &self.state
+    }
+
+    pub fn get_silences_path(&self) -> &PathBuf {
+        &self.silences
+    }
+
+    pub fn get_digest_path(&self) -> &PathBuf {
+        &self.digest
+    }
+
+    pub fn get_deferred_path(&self) -> &PathBuf {
+        &self.deferred
+    }
+
+    pub fn get_outbox_path(&self) -> &PathBuf {
+        &self.outbox
+    }
+
+    pub fn get_socket_path(&self) -> &PathBuf {
+        &self.socket
+    }
+
+    /// Re-reads the rnotifyd and rnotify configs, keeping the current configs if either is invalid,
+    /// or if the http listener changed, as it can't be changed without restarting.
+    pub fn reload(&mut self) -> Result<(), String> {
+        let (routes, job_config) = load_configs(&self.rnotify_config_path, &self.rnotifyd_config_path)?;
+        if job_config.get_http() != self.job_config.get_http() {
+            return Err(String::from("The http listener can't be changed without restarting rnotifyd, keeping the current config."));
+        }
+        self.redactors = create_redactors(&job_config);
+        self.routes = routes;
+        self.job_config = job_config;
+        Ok(())
+    }
 }
 
 pub fn read_configs(parsed: &Matches) -> AllConfig {
@@ -28,7 +86,7 @@ pub fn read_configs(parsed: &Matches) -> AllConfig {
         .map(PathBuf::from)
         .unwrap_or_else(|| rnotifylib::config::get_default_config_path());
 
-    let rnotifyd_config_path = get_string_arg(parsed, RNOTIFYD_CONFIG_ARG);
+    let rnotifyd_config_path: PathBuf = get_string_arg(parsed, RNOTIFYD_CONFIG_ARG).into();
 
     let rnotify_storage_path = parsed.opt_str(RNOTIFY_RUN_LOG_ARG)
         .unwrap_or_else(|| String::from("run_log.txt"));
@@ -36,37 +94,86 @@ pub fn read_configs(parsed: &Matches) -> AllConfig {
 
     let run_log: PathBuf = rnotify_storage_path.into();
 
-    let rnotify_config: rnotifylib::config::Config = {
-        let rnotify_config_str = match fs::read_to_string(rnotify_config_path) {
-            Ok(s) => s,
-            Err(err) => panic!("Error reading rnotify (toml) config file: {}", err),
-        };
-        match toml::from_str(&rnotify_config_str) {
-            Ok(c) => c,
-            Err(err) => panic!("Error parsing rnotify (toml) config file: {}", err),
-        }
-    };
+    let state: PathBuf = parsed.opt_str(RNOTIFYD_STATE_ARG)
+        .unwrap_or_else(|| String::from("job_state.yaml"))
+        .into();
 
-    let rnotifyd_config: rnotifydlib::config::Config = {
-        let path: PathBuf = rnotifyd_config_path.into();
-        if !path.exists() {
-            panic!("Config file: '{:?}' does not exist.", path);
-        }
-        let rnotifyd_config_str = match fs::read_to_string(path) {
-            Ok(s) => s,
-            Err(err) => panic!("Error reading rnotifyd (yaml) config file {}", err),
-        };
-        match serde_yaml::from_str(&rnotifyd_config_str) {
-            Ok(c) => c,
-            Err(err) => panic!("Error parsing rnotifyd (yaml) config file: {}", err),
-        }
+    let silences: PathBuf = parsed.opt_str(RNOTIFYD_SILENCES_ARG)
+        .unwrap_or_else(|| String::from("silences.yaml"))
+        .into();
+
+    let digest: PathBuf = parsed.opt_str(RNOTIFYD_DIGEST_ARG)
+        .unwrap_or_else(|| String::from("digest.yaml"))
+        .into();
+
+    let deferred: PathBuf = parsed.opt_str(RNOTIFYD_DEFERRED_ARG)
+        .unwrap_or_else(|| String::from("deferred.yaml"))
+        .into();
+
+    let outbox: PathBuf = parsed.opt_str(RNOTIFYD_OUTBOX_ARG)
+        .unwrap_or_else(|| String::from("outbox.yaml"))
+        .into();
+
+    let socket: PathBuf = parsed.opt_str(RNOTIFYD_SOCKET_ARG)
+        .unwrap_or_else(|| String::from(DEFAULT_SOCKET))
+        .into();
+
+    let (routes, job_config) = match load_configs(&rnotify_config_path, &rnotifyd_config_path) {
+        Ok(configs) => configs,
+        Err(err) => panic!("{}", err),
     };
 
     AllConfig {
-        rnotify: rnotify_config,
-        job_config: rnotifyd_config,
+        routes,
+        redactors: create_redactors(&job_config),
+        job_config,
+        rnotify_config_path,
+        rnotifyd_config_path,
         run_log,
+        state,
+        silences,
+        digest,
+        deferred,
+        outbox,
+        socket,
+    }
+}
+
+/// Reads the rnotifyd config, and the rnotify configs for each of its routes, including the default.
+fn load_configs(rnotify_config_path: &Path, rnotifyd_config_path: &Path)
+                -> Result<(HashMap<String, rnotifylib::config::Config>, rnotifydlib::config::Config), String> {
+    if !rnotifyd_config_path.exists() {
+        return Err(format!("Config file: '{:?}' does not exist.", rnotifyd_config_path));
+    }
+    let rnotifyd_config_str = fs::read_to_string(rnotifyd_config_path)
+        .map_err(|err| format!("Error reading rnotifyd (yaml) config file {}", err))?;
+    let rnotifyd_config: rnotifydlib::config::Config = serde_yaml::from_str(&rnotifyd_config_str)
+        .map_err(|err| format!("Error parsing rnotifyd (yaml) config file: {}", err))?;
+
+    rnotifyd_config.validate_jobs()
+        .and_then(|_| rnotifyd_config.validate_routes())
+        .and_then(|_| rnotifyd_config.validate_daemon_author())
+        .map_err(|err| format!("Invalid rnotifyd (yaml) config file: {}", err))?;
+    let rnotify_config = read_rnotify_config(rnotify_config_path)?;
+    let mut routes = HashMap::new();
+    for (name, path) in rnotifyd_config.get_routes() {
+        routes.insert(name.clone(), read_rnotify_config(path)?);
     }
+    routes.insert(String::from(DEFAULT_ROUTE), rnotify_config);
+    Ok((routes, rnotifyd_config))
+}
+
+fn create_redactors(job_config: &rnotifydlib::config::Config) -> HashMap<JobDefinitionId, Arc<Redactor>> {
+    job_config.entries().iter()
+        .map(|(id, job)| (id.clone(), Arc::new(job_config.get_redactor(job))))
+        .collect()
+}
+
+fn read_rnotify_config(path: &Path) -> Result<rnotifylib::config::Config, String> {
+    let rnotify_config_str = fs::read_to_string(path)
+        .map_err(|err| format!("Error reading rnotify (toml) config file {:?}: {}", path, err))?;
+    toml::from_str(&rnotify_config_str)
+        .map_err(|err| format!("Error parsing rnotify (toml) config file {:?}: {}", path, err))
 }
 
 fn get_string_arg(matches: &Matches, arg_name: &str) -> String {
@@ -74,4 +181,37 @@ fn get_string_arg(matches: &Matches, arg_name: &str) -> String {
         None => panic!("Missing argument: {}", arg_name),
         Some(s) => s
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reload_invalid_job_id() {
+        let dir = std::env::temp_dir();
+        let rnotifyd_config_path = dir.join(format!("rnotifyd-config-test-{}.yaml", std::process::id()));
+        let example = fs::read_to_string("test/example_config.yaml").unwrap();
+        let job_config: rnotifydlib::config::Config = serde_yaml::from_str(&example).unwrap();
+        fs::write(&rnotifyd_config_path, example.replace("check-devices:", "Check_Devices:")).unwrap();
+
+        let mut config = AllConfig {
+            routes: HashMap::new(),
+            job_config: job_config.clone(),
+            redactors: HashMap::new(),
+            rnotify_config_path: dir.join("rnotify-config-test.toml"),
+            rnotifyd_config_path: rnotifyd_config_path.clone(),
+            run_log: PathBuf::new(),
+            state: PathBuf::new(),
+            silences: PathBuf::new(),
+            digest: PathBuf::new(),
+            deferred: PathBuf::new(),
+            outbox: PathBuf::new(),
+            socket: PathBuf::new(),
+        };
+        let result = config.reload();
+        fs::remove_file(&rnotifyd_config_path).unwrap();
+        let err = result.unwrap_err();
+        assert!(err.contains("Check_Devices"), "{err}");
+        assert_eq!(&job_config, config.get_job_config());
+    }
+}
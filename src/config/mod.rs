@@ -1,28 +1,236 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::fmt::{Display, Formatter};
-use serde::{Serialize, Deserialize, Deserializer};
+use rnotifylib::message::author::Author;
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+use crate::digest::DigestDefinition;
+use crate::fallback::FallbackSink;
 use crate::frequency::Frequency;
-use crate::notify_definition::NotifyDefinition;
+use crate::host;
+use crate::http::HttpDefinition;
+use crate::notification;
+use crate::notify_definition::{DEFAULT_AUTHOR, DEFAULT_ROUTE, NotifyDefinition};
+use crate::outbox::OutboxDefinition;
+use crate::passive::PassiveDefinition;
+use crate::quiet_hours::QuietHours;
+use crate::redact::{RedactionRules, Redactor};
+use crate::silence::MaintenanceWindow;
+use crate::template::{Template, TemplateContext, TemplateVariable};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Config {
-    jobs: HashMap<JobDefinitionId, JobDefinition>
+    jobs: HashMap<JobDefinitionId, JobDefinition>,
+    /// Redaction rules applied to the output of every job.
+    #[serde(default)]
+    redact: RedactionRules,
+    /// Recurring periods in which notifications are suppressed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    maintenance_windows: Vec<MaintenanceWindow>,
+    /// Sends Info and Warn notifications together on a schedule, rather than as they happen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<DigestDefinition>,
+    /// Defers non-critical notifications at night, or other times they shouldn't disturb anyone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quiet_hours: Option<QuietHours>,
+    /// How messages that fail to send are retried.
+    #[serde(default)]
+    outbox: OutboxDefinition,
+    /// Where to record messages that could not be sent to rnotify, see [FallbackSink]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fallback: Vec<FallbackSink>,
+    /// Additional rnotify config files that jobs can choose to send their messages through, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    routes: BTreeMap<String, PathBuf>,
+    /// The author of messages, which can use the same variables as the title.
+    /// Defaults to [DEFAULT_AUTHOR]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<Template>,
+    /// The author of messages from rnotifyd itself, such as the digest, which can only use the hostname variable.
+    /// Defaults to rnotifyd@{{hostname}}
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    daemon_author: Option<Template>,
+    /// The local HTTP listener, needed for passive jobs, and to trigger jobs on demand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    http: Option<HttpDefinition>,
 }
 
 impl Config {
     pub fn entries(&self) -> &HashMap<JobDefinitionId, JobDefinition> {
         &self.jobs
     }
+
+    /// Creates a redactor combining the global rules and the rules of the given job.
+    pub fn get_redactor(&self, job: &JobDefinition) -> Redactor {
+        Redactor::new(&[&self.redact, &job.redact])
+    }
+
+    pub fn get_maintenance_windows(&self) -> &Vec<MaintenanceWindow> {
+        &self.maintenance_windows
+    }
+
+    pub fn get_digest(&self) -> Option<&DigestDefinition> {
+        self.digest.as_ref()
+    }
+
+    pub fn get_outbox(&self) -> &OutboxDefinition {
+        &self.outbox
+    }
+
+    pub fn get_fallback(&self) -> &Vec<FallbackSink> {
+        &self.fallback
+    }
+
+    pub fn get_routes(&self) -> &BTreeMap<String, PathBuf> {
+        &self.routes
+    }
+
+    /// Gets the author template for the given job, which overrides the global author if it has its own.
+    pub fn get_author(&self, job: &JobDefinition) -> Template {
+        job.get_notify_definition().get_author()
+            .or(self.author.as_ref())
+            .cloned()
+            .unwrap_or_else(|| Template::parse(DEFAULT_AUTHOR).expect("Default author should be valid"))
+    }
+
+    /// Gets the author of messages from rnotifyd itself, such as the digest, rather than about a particular job.
+    pub fn get_daemon_author(&self) -> Author {
+        match &self.daemon_author {
+            Some(author) => {
+                let mut context = TemplateContext::new();
+                context.set(TemplateVariable::Hostname, host::get_hostname());
+                Author::parse(author.render(&context))
+            }
+            None => notification::daemon_author(),
+        }
+    }
+
+    pub fn get_http(&self) -> Option<&HttpDefinition> {
+        self.http.as_ref()
+    }
+
+    /// Checks that every job either has a command or is passive, that passive jobs can be pinged,
+    /// that fixed periods aren't zero, and that expected durations have a tolerance above 1.
+    pub fn validate_jobs(&self) -> Result<(), String> {
+        for (id, job) in &self.jobs {
+            match (job.cmd.is_empty(), job.is_passive()) {
+                (true, false) => return Err(format!("Job {id} has no cmd, and is not passive.")),
+                (false, true) => return Err(format!("Job {id} is passive, so can't have a cmd.")),
+                _ => {}
+            }
+            if job.is_passive() && self.http.is_none() {
+                return Err(format!("Job {id} is passive, but there is no http listener for it to be pinged through."));
+            }
+            if let Frequency::FixedPeriod(period) = &job.frequency {
+                if period.as_seconds() == 0 {
+                    return Err(format!("Job {id} has a frequency of zero."));
+                }
+            }
+            if job.get_notify_definition().get_renotify_interval().is_some_and(|interval| interval.as_seconds() == 0) {
+                return Err(format!("Job {id} has a renotify_interval of zero."));
+            }
+            if let Some(expected_duration) = job.get_notify_definition().get_expected_duration() {
+                if expected_duration.get_tolerance() <= 1.0 {
+                    return Err(format!("Job {id} has an expected duration tolerance of {}, which must be more than 1.",
+                                       expected_duration.get_tolerance()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the daemon author only uses variables that don't depend on a job.
+    pub fn validate_daemon_author(&self) -> Result<(), String> {
+        let variables = self.daemon_author.iter().flat_map(|author| author.get_variables());
+        for variable in variables {
+            if variable != TemplateVariable::Hostname {
+                return Err(format!("daemon_author can't use '{}', only 'hostname'.", variable.name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every route used by a job is defined.
+    pub fn validate_routes(&self) -> Result<(), String> {
+        for (id, job) in &self.jobs {
+            for route in job.get_notify_definition().get_routes() {
+                if route != DEFAULT_ROUTE && !self.routes.contains_key(&route) {
+                    return Err(format!("Job {id} uses route '{route}', which is not defined in routes."));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the quiet hours for the given job, which overrides the global quiet hours if it has its own.
+    pub fn get_quiet_hours<'a>(&'a self, job: &'a JobDefinition) -> Option<&'a QuietHours> {
+        job.get_notify_definition().get_quiet_hours().or(self.quiet_hours.as_ref())
+    }
+
+    /// Compares the jobs with those in a new version of the config.
+    pub fn diff_jobs(&self, new: &Config) -> JobChanges {
+        let sorted = |mut ids: Vec<JobDefinitionId>| {
+            ids.sort_by(|a, b| a.id.cmp(&b.id));
+            ids
+        };
+        JobChanges {
+            added: sorted(new.jobs.keys().filter(|id| !self.jobs.contains_key(id)).cloned().collect()),
+            removed: sorted(self.jobs.keys().filter(|id| !new.jobs.contains_key(id)).cloned().collect()),
+            changed: sorted(self.jobs.iter()
+                .filter(|(id, job)| new.jobs.get(id).is_some_and(|new_job| new_job != *job))
+                .map(|(id, _)| id.clone())
+                .collect()),
+        }
+    }
+}
+
+/// The jobs which differ between two versions of the config, each sorted by id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobChanges {
+    added: Vec<JobDefinitionId>,
+    removed: Vec<JobDefinitionId>,
+    changed: Vec<JobDefinitionId>,
+}
+
+impl JobChanges {
+    pub fn get_added(&self) -> &Vec<JobDefinitionId> {
+        &self.added
+    }
+
+    pub fn get_removed(&self) -> &Vec<JobDefinitionId> {
+        &self.removed
+    }
+
+    pub fn get_changed(&self) -> &Vec<JobDefinitionId> {
+        &self.changed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct JobDefinition {
+    /// The command to run, which passive jobs don't have.
+    #[serde(default)]
     cmd: String,
     #[serde(default)] // false by default.
     allow_parallel: bool,
     frequency: Frequency,
     #[serde(rename = "notification")]
     notify_definition: NotifyDefinition,
+    #[serde(default)]
+    redact: RedactionRules,
+    /// Tags that silences and maintenance windows can refer to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Alerts if the job hasn't succeeded since the previous deadline by each of these times,
+    /// such as by 06:00 every weekday.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    must_succeed_by: Option<Frequency>,
+    /// Makes this a passive job, which is pinged over HTTP rather than run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    passive: Option<PassiveDefinition>,
 }
 
 impl JobDefinition {
@@ -41,10 +249,25 @@ impl JobDefinition {
     pub fn allow_parallel(&self) -> bool {
         self.allow_parallel
     }
+
+    pub fn get_tags(&self) -> &Vec<String> {
+        &self.tags
+    }
+
+    pub fn get_passive(&self) -> Option<&PassiveDefinition> {
+        self.passive.as_ref()
+    }
+
+    pub fn is_passive(&self) -> bool {
+        self.passive.is_some()
+    }
+
+    pub fn get_must_succeed_by(&self) -> Option<&Frequency> {
+        self.must_succeed_by.as_ref()
+    }
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
-#[derive(Serialize)]
 pub struct JobDefinitionId {
     id: String
 }
@@ -52,7 +275,7 @@ pub struct JobDefinitionId {
 impl JobDefinitionId {
     pub fn try_new(s: String) -> Result<Self, String> {
         if !inflections::case::is_kebab_case(&s) {
-            return Err("Not camel case.".to_owned());
+            return Err(format!("Invalid job id '{s}', job ids must be kebab case."));
         }
         Ok(Self {
             id: s
@@ -66,21 +289,37 @@ impl Display for JobDefinitionId {
     }
 }
 
+impl Serialize for JobDefinitionId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        self.id.serialize(serializer)
+    }
+}
+
 impl<'de> Deserialize<'de> for JobDefinitionId {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
         let string = String::deserialize(deserializer)?;
-        Ok(JobDefinitionId::try_new(string).expect("Invalid job definition id."))
+        JobDefinitionId::try_new(string).map_err(serde::de::Error::custom)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
+    use chrono::NaiveTime;
+    use rnotifylib::message::author::Author;
     use rnotifylib::message::component::Component;
     use crate::action::ProgramOutputFormat;
     use crate::config::{Config, JobDefinition, JobDefinitionId};
     use crate::frequency::{FixedPeriodInner, Frequency};
-    use crate::notify_definition::NotifyDefinition;
+    use crate::host;
+    use crate::http::HttpDefinition;
+    use crate::metrics::Threshold;
+    use crate::notification;
+    use crate::redact::RedactionRules;
+    use crate::template::Template;
+    use crate::notify_definition::{DEFAULT_AUTHOR, ExpectedDuration, NotifyDefinition};
+    use crate::outbox::OutboxDefinition;
+    use crate::passive::PassiveDefinition;
 
     #[test]
     fn test_config() {
@@ -92,12 +331,118 @@ mod tests {
             cmd: "ping 192.168.0.10".to_string(),
             allow_parallel: false,
             frequency: Frequency::FixedPeriod(FixedPeriodInner::new(0, 30, 0)),
-            notify_definition: NotifyDefinition::new("Ping 192.168.0.10".to_string(), Component::from("ping"),
+            notify_definition: NotifyDefinition::new(Template::parse("Ping 192.168.0.10").unwrap(), Component::from("ping"),
                                                      false, ProgramOutputFormat::StdoutIfSuccess),
+            redact: RedactionRules::default(),
+            tags: vec![],
+            must_succeed_by: None,
+            passive: None,
         };
         jobs.insert(JobDefinitionId::try_new("check-devices".to_string()).unwrap(), job);
-        let expected = Config { jobs };
+
+        let mut thresholds = BTreeMap::new();
+        thresholds.insert("disk_used_pct".to_string(), Threshold::new(Some(80.0), Some(90.0)));
+        let job = JobDefinition {
+            cmd: "./check_disk.sh".to_string(),
+            allow_parallel: false,
+            frequency: Frequency::Daily { time: NaiveTime::from_hms_opt(8, 0, 0).unwrap() },
+            notify_definition: NotifyDefinition::new(Template::parse("{{hostname}} disk usage").unwrap(), Component::from("disk"),
+                                                     false, ProgramOutputFormat::SimpleIfSuccess)
+                .with_thresholds(thresholds)
+                .with_summary(Template::parse("{{stdout_first_line}}").unwrap()),
+            redact: RedactionRules::default(),
+            tags: vec!["storage".to_string()],
+            must_succeed_by: Some(Frequency::Daily { time: NaiveTime::from_hms_opt(9, 0, 0).unwrap() }),
+            passive: None,
+        };
+        jobs.insert(JobDefinitionId::try_new("disk-usage".to_string()).unwrap(), job);
+
+        let job = JobDefinition {
+            cmd: String::new(),
+            allow_parallel: false,
+            frequency: Frequency::Daily { time: NaiveTime::from_hms_opt(2, 0, 0).unwrap() },
+            notify_definition: NotifyDefinition::new(Template::parse("Offsite backup").unwrap(), Component::from("backup"),
+                                                     false, ProgramOutputFormat::SimpleIfSuccess),
+            redact: RedactionRules::default(),
+            tags: vec![],
+            must_succeed_by: None,
+            passive: Some(PassiveDefinition::new(FixedPeriodInner::new(0, 30, 0))),
+        };
+        jobs.insert(JobDefinitionId::try_new("offsite-backup".to_string()).unwrap(), job);
+        let expected = Config { jobs, redact: RedactionRules::default(), maintenance_windows: vec![], digest: None, quiet_hours: None, outbox: OutboxDefinition::default(), fallback: vec![], routes: BTreeMap::new(), author: None, daemon_author: None, http: Some(HttpDefinition::new("127.0.0.1:8075".to_string()).with_token("change-me".to_string())) };
 
         assert_eq!(expected, config);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_get_author() {
+        let s = std::fs::read_to_string("test/example_config.yaml").expect("Failed to read file.");
+        let mut config: Config = serde_yaml::from_str(&s).expect("Failed to deserialize config");
+        let id = JobDefinitionId::try_new("check-devices".to_string()).unwrap();
+        assert_eq!(Template::parse(DEFAULT_AUTHOR).unwrap(), config.get_author(&config.jobs[&id]));
+        assert_eq!(notification::daemon_author(), config.get_daemon_author());
+
+        // The global author is for messages about jobs, so doesn't change the daemon author.
+        config.author = Some(Template::parse("cron@{{hostname}}/{{job_id}}").unwrap());
+        assert_eq!(Template::parse("cron@{{hostname}}/{{job_id}}").unwrap(), config.get_author(&config.jobs[&id]));
+        assert_eq!(notification::daemon_author(), config.get_daemon_author());
+
+        let job = config.jobs.get_mut(&id).unwrap();
+        job.notify_definition = job.notify_definition.clone().with_author(Template::parse("pinger").unwrap());
+        assert_eq!(Template::parse("pinger").unwrap(), config.get_author(&config.jobs[&id]));
+
+        config.daemon_author = Some(Template::parse("cron@{{hostname}}").unwrap());
+        assert_eq!(Ok(()), config.validate_daemon_author());
+        assert_eq!(Author::parse(format!("cron@{}", host::get_hostname())), config.get_daemon_author());
+        assert_eq!(Template::parse("pinger").unwrap(), config.get_author(&config.jobs[&id]));
+
+        config.daemon_author = Some(Template::parse("cron@{{hostname}}/{{job_id}}").unwrap());
+        assert!(config.validate_daemon_author().is_err());
+    }
+
+    #[test]
+    fn test_validate_jobs() {
+        let s = std::fs::read_to_string("test/example_config.yaml").expect("Failed to read file.");
+        let mut config: Config = serde_yaml::from_str(&s).expect("Failed to deserialize config");
+        assert_eq!(Ok(()), config.validate_jobs());
+
+        let job = config.jobs.get_mut(&JobDefinitionId::try_new("check-devices".to_string()).unwrap()).unwrap();
+        job.notify_definition = job.notify_definition.clone().with_expected_duration(ExpectedDuration::new(None, 1.0));
+        assert!(config.validate_jobs().is_err());
+
+        let mut config: Config = serde_yaml::from_str(&s).expect("Failed to deserialize config");
+        let job = config.jobs.get_mut(&JobDefinitionId::try_new("check-devices".to_string()).unwrap()).unwrap();
+        job.frequency = Frequency::FixedPeriod(FixedPeriodInner::new(0, 0, 0));
+        assert!(config.validate_jobs().is_err());
+
+        let mut config: Config = serde_yaml::from_str(&s).expect("Failed to deserialize config");
+        let job = config.jobs.get_mut(&JobDefinitionId::try_new("check-devices".to_string()).unwrap()).unwrap();
+        job.notify_definition = job.notify_definition.clone().with_renotify_interval(FixedPeriodInner::new(0, 0, 0));
+        assert!(config.validate_jobs().is_err());
+    }
+
+    #[test]
+    fn test_fixed_period_fields_required() {
+        let yaml = "type: FixedPeriod\nminutes: 30\n";
+        assert!(serde_yaml::from_str::<Frequency>(yaml).is_err());
+        let yaml = "type: FixedPeriod\nhours: 0\nminutes: 30\nseconds: 0\n";
+        assert_eq!(Frequency::FixedPeriod(FixedPeriodInner::new(0, 30, 0)), serde_yaml::from_str(yaml).unwrap());
+    }
+
+    #[test]
+    fn test_diff_jobs() {
+        let s = std::fs::read_to_string("test/example_config.yaml").expect("Failed to read file.");
+        let old: Config = serde_yaml::from_str(&s).expect("Failed to deserialize config");
+        let mut new = old.clone();
+        let id = |id: &str| JobDefinitionId::try_new(id.to_string()).unwrap();
+        let job = new.jobs.remove(&id("check-devices")).unwrap();
+        new.jobs.insert(id("check-router"), job);
+        new.jobs.get_mut(&id("disk-usage")).unwrap().allow_parallel = true;
+
+        let changes = old.diff_jobs(&new);
+        assert_eq!(&vec![id("check-router")], changes.get_added());
+        assert_eq!(&vec![id("check-devices")], changes.get_removed());
+        assert_eq!(&vec![id("disk-usage")], changes.get_changed());
+        assert!(old.diff_jobs(&old).is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Serialize, Deserialize};
+use crate::config::JobDefinitionId;
+use crate::duration_format::parse_duration;
+
+pub const USAGE: &str = "Usage: rnotifyd ctl [--socket SOCKET] <command>
+
+Commands:
+    status                  Show what the daemon is doing
+    list                    List the jobs, and the result of their last run
+    next                    Show when each job will next run
+    run <job>               Run a job now
+    pause <job> [duration]  Stop running a job on its schedule, until it is resumed or for the duration
+    resume <job>            Start running a paused job on its schedule again
+    silence [--job <job>] [--tag <tag>] <duration> [reason]
+                            Suppress notifications, e.g. silence --job backup 2h disk replacement
+    reload                  Re-read the config";
+
+/// A command sent to the daemon over the control socket, as a single line of JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ControlRequest {
+    Status,
+    List,
+    Next,
+    Run { job: JobDefinitionId },
+    Pause {
+        job: JobDefinitionId,
+        /// How long to pause for, in seconds, or None to pause until resumed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration: Option<u64>,
+    },
+    Resume { job: JobDefinitionId },
+    Silence {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        job: Option<JobDefinitionId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        /// How long to silence for, in seconds.
+        duration: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Reload,
+}
+
+impl ControlRequest {
+    /// Parses the arguments given after `rnotifyd ctl`, e.g. ["run", "backup"]
+    pub fn parse_args(args: &[String]) -> Result<Self, String> {
+        let (command, args) = args.split_first().ok_or("Missing command")?;
+        let parse_job = |job: &String| JobDefinitionId::try_new(job.clone()).map_err(|err| format!("Invalid job id '{job}': {err}"));
+        let job = || -> Result<JobDefinitionId, String> {
+            match args {
+                [job] => parse_job(job),
+                _ => Err(format!("{command} takes a single job id")),
+            }
+        };
+        let no_args = |request: ControlRequest| {
+            if args.is_empty() { Ok(request) } else { Err(format!("{command} takes no arguments")) }
+        };
+        match command.as_str() {
+            "status" => no_args(ControlRequest::Status),
+            "list" => no_args(ControlRequest::List),
+            "next" => no_args(ControlRequest::Next),
+            "reload" => no_args(ControlRequest::Reload),
+            "run" => Ok(ControlRequest::Run { job: job()? }),
+            "pause" => match args {
+                [job] => Ok(ControlRequest::Pause { job: parse_job(job)?, duration: None }),
+                [job, duration] => Ok(ControlRequest::Pause { job: parse_job(job)?, duration: Some(parse_duration(duration)?) }),
+                _ => Err(String::from("pause takes a job id, and optionally a duration")),
+            },
+            "resume" => Ok(ControlRequest::Resume { job: job()? }),
+            "silence" => parse_silence(args),
+            _ => Err(format!("Unknown command '{command}'")),
+        }
+    }
+}
+
+fn parse_silence(args: &[String]) -> Result<ControlRequest, String> {
+    let mut job = None;
+    let mut tag = None;
+    let mut args = args.iter();
+    let duration = loop {
+        match args.next().map(|arg| arg.as_str()) {
+            Some("--job") => {
+                let id = args.next().ok_or("--job needs a job id")?;
+                job = Some(JobDefinitionId::try_new(id.clone()).map_err(|err| format!("Invalid job id '{id}': {err}"))?);
+            }
+            Some("--tag") => tag = Some(args.next().ok_or("--tag needs a tag")?.clone()),
+            Some(duration) => break parse_duration(duration)?,
+            None => return Err(String::from("silence needs a duration, such as 2h")),
+        }
+    };
+    let reason = Some(args.cloned().collect::<Vec<_>>().join(" ")).filter(|reason| !reason.is_empty());
+    Ok(ControlRequest::Silence { job, tag, duration, reason })
+}
+
+/// The daemon's response to a [ControlRequest], as a single line of JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlResponse {
+    ok: bool,
+    /// Text to show to the user.
+    output: String,
+}
+
+impl ControlResponse {
+    pub fn ok(output: String) -> Self {
+        Self {
+            ok: true,
+            output,
+        }
+    }
+
+    pub fn error(output: String) -> Self {
+        Self {
+            ok: false,
+            output,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.ok
+    }
+
+    pub fn get_output(&self) -> &str {
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ControlRequest, String> {
+        ControlRequest::parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let job = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        assert_eq!(Ok(ControlRequest::Status), parse(&["status"]));
+        assert_eq!(Ok(ControlRequest::Run { job: job.clone() }), parse(&["run", "backup"]));
+        assert!(parse(&["run"]).is_err());
+        assert!(parse(&["status", "now"]).is_err());
+        assert_eq!(Ok(ControlRequest::Pause { job: job.clone(), duration: Some(30 * 60) }), parse(&["pause", "backup", "30m"]));
+        assert_eq!(Ok(ControlRequest::Silence { job: Some(job), tag: None, duration: 2 * 60 * 60, reason: Some("disk replacement".to_owned()) }),
+                   parse(&["silence", "--job", "backup", "2h", "disk", "replacement"]));
+        assert!(parse(&["silence", "--tag", "storage"]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let request = ControlRequest::Silence { job: None, tag: Some("storage".to_owned()), duration: 60, reason: None };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(r#"{"command":"silence","tag":"storage","duration":60}"#, json);
+        assert_eq!(request, serde_json::from_str(&json).unwrap());
+    }
+}
@@ -0,0 +1,58 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use getopts::{Options, ParsingStyle};
+use rnotifydlib::control;
+use rnotifydlib::control::{ControlRequest, ControlResponse};
+use crate::{DEFAULT_SOCKET, RNOTIFYD_SOCKET_ARG};
+
+/// Runs `rnotifyd ctl`, sending a command to the running daemon and printing its response.
+/// args: the arguments after ctl. Gives the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
+    opts.optopt("", RNOTIFYD_SOCKET_ARG, "The control socket of the daemon.", "SOCKET");
+    let parsed = match opts.parse(args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{err}\n{}", control::USAGE);
+            return 2;
+        }
+    };
+    let request = match ControlRequest::parse_args(&parsed.free) {
+        Ok(request) => request,
+        Err(err) => {
+            eprintln!("{err}\n{}", control::USAGE);
+            return 2;
+        }
+    };
+    let socket: PathBuf = parsed.opt_str(RNOTIFYD_SOCKET_ARG)
+        .unwrap_or_else(|| String::from(DEFAULT_SOCKET))
+        .into();
+
+    match send(&socket, &request) {
+        Ok(response) if response.is_ok() => {
+            println!("{}", response.get_output());
+            0
+        }
+        Ok(response) => {
+            eprintln!("{}", response.get_output());
+            1
+        }
+        Err(err) => {
+            eprintln!("Failed to send command to rnotifyd through {:?}: {err}", socket);
+            1
+        }
+    }
+}
+
+fn send(socket: &Path, request: &ControlRequest) -> Result<ControlResponse, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|err| err.to_string())?;
+    let mut line = serde_json::to_string(request).map_err(|err| err.to_string())?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).map_err(|err| err.to_string())?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).map_err(|err| err.to_string())?;
+    serde_json::from_str(&response).map_err(|err| format!("Invalid response '{}': {err}", response.trim()))
+}
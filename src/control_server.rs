@@ -0,0 +1,136 @@
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::path::Path;
+use log::debug;
+#[cfg(unix)]
+use log::{error, info, warn};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(unix)]
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use rnotifydlib::control::{ControlRequest, ControlResponse};
+
+/// A command from the control socket, which is handled by the main loop.
+#[derive(Debug)]
+pub struct ControlCommand {
+    request: ControlRequest,
+    reply: oneshot::Sender<ControlResponse>,
+}
+
+impl ControlCommand {
+    /// Creates a command, and the receiver for its response.
+    pub fn new(request: ControlRequest) -> (Self, oneshot::Receiver<ControlResponse>) {
+        let (reply, response) = oneshot::channel();
+        (Self { request, reply }, response)
+    }
+
+    pub fn get_request(&self) -> &ControlRequest {
+        &self.request
+    }
+
+    pub fn respond(self, response: ControlResponse) {
+        if self.reply.send(response).is_err() {
+            debug!("Control client disconnected before it was responded to.");
+        }
+    }
+}
+
+/// Starts listening on the control socket, passing the commands to the sender.
+#[cfg(unix)]
+pub fn spawn_server(path: &Path, sender: Sender<ControlCommand>) {
+    // A socket left behind by a previous run would stop us binding.
+    // Anything else, or a socket that another rnotifyd is still listening on, is left alone.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            error!("Not creating control socket {:?}, as a file that isn't a socket is already there.", path);
+            return;
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            error!("Not creating control socket {:?}, as it is in use by another process.", path);
+            return;
+        }
+        if let Err(err) = std::fs::remove_file(path) {
+            warn!("Failed to remove old control socket {:?}: {err}", path);
+        }
+    }
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to create control socket {:?}: {err}", path);
+            return;
+        }
+    };
+    info!("Listening for commands on {:?}", path);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, sender.clone()));
+                }
+                Err(err) => warn!("Failed to accept control connection: {err}"),
+            }
+        }
+    });
+}
+
+/// Reloads the config whenever rnotifyd receives SIGHUP.
+#[cfg(unix)]
+pub fn spawn_hangup_listener(sender: Sender<ControlCommand>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Failed to listen for SIGHUP: {err}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading config.");
+            let (command, response) = ControlCommand::new(ControlRequest::Reload);
+            if sender.send(command).await.is_err() {
+                return;
+            }
+            // The main loop logs the result of reloading.
+            let _ = response.await;
+        }
+    });
+}
+
+/// Removes the control socket, when shutting down.
+#[cfg(unix)]
+pub fn remove_socket(path: &Path) {
+    if let Err(err) = std::fs::remove_file(path) {
+        debug!("Failed to remove control socket {:?}: {err}", path);
+    }
+}
+
+#[cfg(unix)]
+async fn handle_connection(stream: UnixStream, sender: Sender<ControlCommand>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if let Err(err) = reader.read_line(&mut line).await {
+        debug!("Failed to read control command: {err}");
+        return;
+    }
+    let response = match serde_json::from_str(&line) {
+        Err(err) => ControlResponse::error(format!("Invalid command: {err}")),
+        Ok(request) => {
+            let (command, response) = ControlCommand::new(request);
+            match sender.send(command).await {
+                Ok(()) => response.await.unwrap_or_else(|_| ControlResponse::error(String::from("Command was not handled"))),
+                Err(_) => ControlResponse::error(String::from("Not accepting commands")),
+            }
+        }
+    };
+    let mut response = serde_json::to_string(&response).expect("Failed to serialize control response");
+    response.push('\n');
+    if let Err(err) = reader.into_inner().write_all(response.as_bytes()).await {
+        debug!("Failed to write control response: {err}");
+    }
+}
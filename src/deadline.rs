@@ -0,0 +1,112 @@
+use serde::{Serialize, Deserialize};
+use crate::frequency::Frequency;
+use crate::job_state::JobState;
+
+/// The next time by which a job must have succeeded, and the time the success must have happened after,
+/// which is the previous deadline, or when the deadline was first set.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PendingDeadline {
+    since: u64,
+    due: u64,
+}
+
+impl PendingDeadline {
+    pub fn new(since: u64, due: u64) -> Self {
+        Self {
+            since,
+            due,
+        }
+    }
+
+    pub fn get_since(&self) -> u64 {
+        self.since
+    }
+
+    pub fn get_due(&self) -> u64 {
+        self.due
+    }
+
+    /// Whether the job succeeded between the start of the deadline, and the deadline.
+    pub fn is_met(&self, state: &JobState) -> bool {
+        state.has_succeeded_between(self.since, self.due)
+    }
+}
+
+/// The result of checking a job's deadline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadlineCheck {
+    /// The deadline which has passed without the job succeeding, if one has.
+    missed: Option<PendingDeadline>,
+    /// The deadline to check next.
+    next: PendingDeadline,
+}
+
+impl DeadlineCheck {
+    pub fn get_missed(&self) -> Option<&PendingDeadline> {
+        self.missed.as_ref()
+    }
+
+    pub fn get_next(&self) -> &PendingDeadline {
+        &self.next
+    }
+}
+
+/// Checks whether the pending deadline has passed, and whether the job succeeded before it.
+/// If no deadline is pending, the first deadline after now is used.
+/// If several deadlines have passed since the last check, such as while rnotifyd wasn't running,
+/// only the pending deadline is checked.
+pub fn check(must_succeed_by: &Frequency, pending: Option<PendingDeadline>, state: &JobState, now: u64) -> DeadlineCheck {
+    let pending = match pending {
+        Some(pending) => pending,
+        None => {
+            return DeadlineCheck { missed: None, next: PendingDeadline::new(now, must_succeed_by.next_after(now)) };
+        }
+    };
+    if now < pending.due {
+        return DeadlineCheck { missed: None, next: pending };
+    }
+    let missed = Some(pending).filter(|pending| !pending.is_met(state));
+    let next = PendingDeadline::new(pending.due, must_succeed_by.next_after(now.max(pending.due)));
+    DeadlineCheck { missed, next }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, NaiveTime, TimeZone, Weekday};
+    use crate::config::JobDefinitionId;
+    use crate::job_state::{JobStates, ResultKind, RunRecord};
+    use crate::metrics::Metrics;
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let must_succeed_by = Frequency::Weekly {
+            days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+            time: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        };
+        let id = JobDefinitionId::try_new("nightly-export".to_owned()).unwrap();
+        let mut states = JobStates::default();
+        // Monday 2022-12-05 01:00
+        let monday = Local.with_ymd_and_hms(2022, 12, 5, 1, 0, 0).unwrap().timestamp() as u64;
+
+        let first = check(&must_succeed_by, None, &states.get_or_default(&id), monday);
+        assert_eq!(None, first.get_missed());
+        assert_eq!(monday + 5 * 60 * 60, first.get_next().get_due());
+
+        let not_due = check(&must_succeed_by, Some(*first.get_next()), &states.get_or_default(&id), monday + 60 * 60);
+        assert_eq!(None, not_due.get_missed());
+        assert_eq!(first.get_next(), not_due.get_next());
+
+        // Failed, then didn't finish until after the deadline.
+        states.record(id.clone(), RunRecord::new(monday + 60, ResultKind::Failed, Metrics::new()).with_duration(60));
+        states.record(id.clone(), RunRecord::new(monday + 4 * 60 * 60, ResultKind::Ok, Metrics::new()).with_duration(2 * 60 * 60));
+        let after = monday + 5 * 60 * 60 + 30;
+        let missed = check(&must_succeed_by, Some(*first.get_next()), &states.get_or_default(&id), after);
+        assert_eq!(Some(first.get_next()), missed.get_missed());
+        assert_eq!(PendingDeadline::new(monday + 5 * 60 * 60, monday + 29 * 60 * 60), *missed.get_next());
+
+        // The late success counts towards Tuesday's deadline.
+        let met = check(&must_succeed_by, Some(*missed.get_next()), &states.get_or_default(&id), monday + 29 * 60 * 60);
+        assert_eq!(None, met.get_missed());
+    }
+}
@@ -0,0 +1,88 @@
+use chrono::{Local, TimeZone};
+use rnotifydlib::config::Config;
+use rnotifydlib::duration_format::format_duration;
+use rnotifydlib::job_state::JobStates;
+use rnotifydlib::trigger::TriggeredRuns;
+use crate::dispatch::Dispatcher;
+use crate::next_run::NextRun;
+use crate::running_jobs::RunningJobs;
+
+/// Describes what the daemon is doing, for `rnotifyd ctl status`
+pub fn describe_status(job_config: &Config, job_states: &JobStates, running: &RunningJobs, triggered: &TriggeredRuns,
+                       dispatcher: &Dispatcher, started: u64, now: u64) -> String {
+    let mut running_jobs: Vec<String> = running.get_running().iter()
+        .filter_map(|(id, starts)| starts.iter().min().map(|earliest| (id, starts.len(), earliest)))
+        .map(|(id, count, earliest)| match count {
+            1 => format!("{id} ({})", format_duration(now.saturating_sub(*earliest))),
+            _ => format!("{id} x{count} ({})", format_duration(now.saturating_sub(*earliest))),
+        })
+        .collect();
+    running_jobs.sort();
+    let mut paused: Vec<String> = job_config.entries().keys()
+        .filter(|id| job_states.is_paused(id))
+        .map(|id| id.to_string())
+        .collect();
+    paused.sort();
+
+    let mut lines = vec![
+        format!("Up for {}", format_duration(now.saturating_sub(started))),
+        format!("Jobs: {}", job_config.entries().len()),
+        format!("Running: {}", if running_jobs.is_empty() { String::from("none") } else { running_jobs.join(", ") }),
+        format!("Paused: {}", if paused.is_empty() { String::from("none") } else { paused.join(", ") }),
+        format!("Triggered runs queued: {}", triggered.get_queued().len()),
+    ];
+    lines.extend(dispatcher.describe());
+    lines.join("\n")
+}
+
+/// Lists the jobs and the result of their last run, for `rnotifyd ctl list`
+pub fn describe_jobs(job_config: &Config, job_states: &JobStates, running: &RunningJobs, now: u64) -> String {
+    let mut ids: Vec<_> = job_config.entries().keys().collect();
+    ids.sort_by_key(|id| id.to_string());
+    let width = ids.iter().map(|id| id.to_string().len()).max().unwrap_or_default();
+    ids.into_iter()
+        .map(|id| {
+            let definition = &job_config.entries()[id];
+            let mut parts = vec![];
+            if definition.is_passive() {
+                parts.push(String::from("passive"));
+            }
+            match job_states.get(id).and_then(|state| state.get_last_run()) {
+                Some(run) => parts.push(format!("{} {} ago", run.get_result().type_str(), format_duration(now.saturating_sub(run.get_started())))),
+                None => parts.push(String::from("never run")),
+            }
+            if let Some(pause) = job_states.get(id).and_then(|state| state.get_pause()) {
+                match pause.get_until() {
+                    Some(until) => parts.push(format!("paused for {}", format_duration(until.saturating_sub(now)))),
+                    None => parts.push(String::from("paused")),
+                }
+            }
+            if let Some(started) = running.get_earliest(id) {
+                parts.push(format!("running for {}", format_duration(now.saturating_sub(started))));
+            }
+            format!("{:width$}  {}", id.to_string(), parts.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Lists when each job will next run, soonest first, for `rnotifyd ctl next`
+/// Paused jobs are shown with when they would run if they weren't paused.
+pub fn describe_next_runs(job_config: &Config, job_states: &JobStates, next_run: &NextRun) -> String {
+    let mut next: Vec<_> = job_config.entries().iter()
+        .map(|(id, definition)| (next_run.get(id), id, definition.is_passive()))
+        .collect();
+    next.sort_by_key(|(time, id, _)| (time.unwrap_or(u64::MAX), id.to_string()));
+    next.into_iter()
+        .map(|(time, id, passive)| {
+            let time = time.filter(|time| *time != u64::MAX)
+                .and_then(|time| Local.timestamp_opt(time as i64, 0).single())
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| String::from("unknown"));
+            let passive = if passive { " (ping due)" } else { "" };
+            let paused = if job_states.is_paused(id) { " (paused)" } else { "" };
+            format!("{time}  {id}{passive}{paused}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
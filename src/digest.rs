@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use chrono::{DateTime, Local};
+use log::warn;
+use rnotifylib::message::author::Author;
+use rnotifylib::message::formatted_detail::Style;
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
+use rnotifylib::message::{Level, Message};
+use serde::{Serialize, Deserialize};
+use crate::config::JobDefinitionId;
+use crate::frequency::Frequency;
+use crate::job_state::ResultKind;
+use crate::notification::Notification;
+use crate::notify_definition::DEFAULT_ROUTE;
+
+/// Holds back non-critical notifications, and sends them together as one message on a schedule.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct DigestDefinition {
+    frequency: Frequency,
+    /// Whether every job uses the digest unless it sets `digest: false`.
+    /// Otherwise, only jobs that set `digest: true` use it.
+    #[serde(default)]
+    all_jobs: bool,
+}
+
+impl DigestDefinition {
+    pub fn new(frequency: Frequency, all_jobs: bool) -> Self {
+        Self {
+            frequency,
+            all_jobs,
+        }
+    }
+
+    /// Whether a notification should be held back for the digest.
+    /// job_digest: the job's own digest setting.
+    pub fn includes(&self, notification: &Notification, job_digest: Option<bool>) -> bool {
+        is_digestible(notification.get_level()) && job_digest.unwrap_or(self.all_jobs)
+    }
+
+    /// The next time the digest should be sent after now.
+    pub fn next_send(&self, now: &DateTime<Local>) -> u64 {
+        self.frequency.next(&(*now + chrono::Duration::seconds(1)), Some(now.timestamp() as u64))
+    }
+}
+
+/// Only non-critical levels are held back, failures are always sent immediately.
+fn is_digestible(level: &Level) -> bool {
+    matches!(level, Level::Info | Level::Warn)
+}
+
+/// A notification that has been held back, to be sent as part of a combined message.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct DigestEntry {
+    job: JobDefinitionId,
+    result: ResultKind,
+    time: DateTime<Local>,
+    reason: String,
+    /// The routes the notification would have been sent through.
+    #[serde(default = "default_routes")]
+    routes: Vec<String>,
+}
+
+fn default_routes() -> Vec<String> {
+    vec![String::from(DEFAULT_ROUTE)]
+}
+
+impl DigestEntry {
+    pub fn new(notification: &Notification, routes: Vec<String>, now: &DateTime<Local>) -> Self {
+        Self {
+            job: notification.get_job_id().clone(),
+            result: notification.get_result(),
+            time: *now,
+            reason: notification.get_title().to_owned(),
+            routes,
+        }
+    }
+
+    pub fn get_routes(&self) -> &Vec<String> {
+        &self.routes
+    }
+}
+
+/// The notifications held back for the next digest.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Digest {
+    #[serde(default)]
+    entries: Vec<DigestEntry>,
+    /// Unix time to send the digest at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_send: Option<u64>,
+}
+
+impl Digest {
+    pub fn add(&mut self, notification: &Notification, routes: Vec<String>, now: &DateTime<Local>) {
+        self.entries.push(DigestEntry::new(notification, routes, now));
+    }
+
+    pub fn get_entries(&self) -> &Vec<DigestEntry> {
+        &self.entries
+    }
+
+    pub fn get_next_send(&self) -> Option<u64> {
+        self.next_send
+    }
+
+    pub fn set_next_send(&mut self, next_send: u64) {
+        self.next_send = Some(next_send);
+    }
+
+    /// Removes all held back notifications, giving a message listing them for each route they are sent through.
+    pub fn take_messages(&mut self, author: &Author) -> Vec<(String, Message)> {
+        let entries = std::mem::take(&mut self.entries);
+        create_combined_messages("Digest", &entries, author)
+    }
+
+    pub fn read_from_string(s: &str) -> Result<Digest, String> {
+        serde_yaml::from_str(s).map_err(|err| format!("Error parsing digest: {err}"))
+    }
+
+    pub fn write_to_string(&self) -> String {
+        serde_yaml::to_string(self).expect("Failed to serialize digest")
+    }
+}
+
+/// Creates a message for each route, listing the entries sent through that route,
+/// see [create_combined_message]
+pub fn create_combined_messages(name: &str, entries: &[DigestEntry], author: &Author) -> Vec<(String, Message)> {
+    let mut by_route: BTreeMap<&str, Vec<DigestEntry>> = BTreeMap::new();
+    for entry in entries {
+        for route in &entry.routes {
+            by_route.entry(route).or_default().push(entry.clone());
+        }
+    }
+    by_route.into_iter()
+        .map(|(route, entries)| (route.to_owned(), create_combined_message(name, &entries, author)))
+        .collect()
+}
+
+/// Creates a single message listing the given entries in a table,
+/// titled e.g. "Digest: 3 results from 2 jobs".
+pub fn create_combined_message(name: &str, entries: &[DigestEntry], author: &Author) -> Message {
+    let jobs = {
+        let mut jobs: Vec<&JobDefinitionId> = entries.iter().map(|e| &e.job).collect();
+        jobs.sort_by_key(|job| job.to_string());
+        jobs.dedup();
+        jobs.len()
+    };
+    let title = format!("{name}: {} results from {} jobs", entries.len(), jobs);
+    let table = format_table(entries);
+    let detail = MessageDetailBuilder::with_raw(table.clone())
+        .section("Results", |s| {
+            s.append_styled(table, Style::Monospace);
+        })
+        .build();
+    let level = match entries.iter().all(|e| e.result.is_ok()) {
+        true => Level::Info,
+        false => Level::Warn,
+    };
+    Message::new(level, Some(title), detail, None,
+                 author.clone(),
+                 Local::now().timestamp_millis())
+}
+
+fn format_table(entries: &[DigestEntry]) -> String {
+    let mut rows = vec![[String::from("Job"), String::from("Result"), String::from("Time"), String::from("Reason")]];
+    rows.extend(entries.iter().map(|e| [
+        e.job.to_string(),
+        e.result.type_str().to_owned(),
+        e.time.format("%Y-%m-%d %H:%M").to_string(),
+        e.reason.clone(),
+    ]));
+    let mut widths = [0; 3];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    rows.iter()
+        .map(|row| format!("{:w0$}  {:w1$}  {:w2$}  {}", row[0], row[1], row[2], row[3],
+                           w0 = widths[0], w1 = widths[1], w2 = widths[2]))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn read_digest(path: &PathBuf) -> Digest {
+    if !path.exists() {
+        return Digest::default();
+    }
+    let digest_str = fs::read_to_string(path).expect("Failed to read digest");
+    match Digest::read_from_string(&digest_str) {
+        Ok(digest) => digest,
+        Err(err) => {
+            warn!("{err}, starting with an empty digest.");
+            Digest::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use crate::notification;
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let time = Local.with_ymd_and_hms(2022, 12, 6, 8, 0, 0).unwrap();
+        let entries = vec![
+            DigestEntry { job: JobDefinitionId::try_new("ping".to_owned()).unwrap(), result: ResultKind::Ok,
+                time, reason: "Ping 192.168.0.10".to_owned(), routes: default_routes() },
+            DigestEntry { job: JobDefinitionId::try_new("disk-usage".to_owned()).unwrap(), result: ResultKind::Warning,
+                time, reason: "Disk 85% full".to_owned(), routes: default_routes() },
+        ];
+        let expected = "Job         Result   Time              Reason\n\
+                        ping        ok       2022-12-06 08:00  Ping 192.168.0.10\n\
+                        disk-usage  warning  2022-12-06 08:00  Disk 85% full";
+        assert_eq!(expected, format_table(&entries));
+    }
+
+    #[test]
+    fn test_routes() {
+        let time = Local.with_ymd_and_hms(2022, 12, 6, 8, 0, 0).unwrap();
+        let entry = |job: &str, routes: &[&str]| DigestEntry {
+            job: JobDefinitionId::try_new(job.to_owned()).unwrap(), result: ResultKind::Warning, time,
+            reason: "Slow".to_owned(), routes: routes.iter().map(|route| route.to_string()).collect(),
+        };
+        let mut digest = Digest {
+            entries: vec![entry("ping", &["chat"]), entry("disk-usage", &[DEFAULT_ROUTE, "chat"])],
+            next_send: None,
+        };
+
+        let messages = digest.take_messages(&notification::daemon_author());
+        let titles: Vec<(&str, Option<String>)> = messages.iter()
+            .map(|(route, message)| (route.as_str(), message.get_title().clone()))
+            .collect();
+        assert_eq!(vec![("chat", Some("Digest: 2 results from 2 jobs".to_owned())),
+                        (DEFAULT_ROUTE, Some("Digest: 1 results from 1 jobs".to_owned()))], titles);
+        assert!(digest.take_messages(&notification::daemon_author()).is_empty());
+    }
+}
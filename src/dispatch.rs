@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc::Sender;
+use rnotifylib::message::author::Author;
+use rnotifylib::message::Message;
+use rnotifydlib::config::Config;
+use rnotifydlib::digest;
+use rnotifydlib::digest::Digest;
+use rnotifydlib::duration_format::format_duration;
+use rnotifydlib::fallback::FallbackSink;
+use rnotifydlib::notification::Notification;
+use rnotifydlib::notify_definition::DEFAULT_ROUTE;
+use rnotifydlib::outbox;
+use rnotifydlib::outbox::Outbox;
+use rnotifydlib::quiet_hours;
+use rnotifydlib::quiet_hours::Deferred;
+use rnotifydlib::silence::{EndedSilence, Silence};
+use crate::all_config::AllConfig;
+use crate::silence_store::SilenceStore;
+
+/// Decides what happens to each notification: whether it is sent now,
+/// suppressed by a silence, held back for the digest, or deferred until quiet hours end.
+/// Messages are sent via the outbox, so those that fail to send are retried,
+/// and are written to the fallback sinks when they first fail, or are given up on.
+pub struct Dispatcher {
+    /// The rnotify configs to send through, by route name.
+    routes: HashMap<String, rnotifylib::config::Config>,
+    silences: SilenceStore,
+    digest: Digest,
+    digest_path: PathBuf,
+    deferred: Deferred,
+    deferred_path: PathBuf,
+    outbox: Outbox,
+    outbox_path: PathBuf,
+    delivery_sender: Sender<Delivery>,
+}
+
+/// The result of trying to send a message from the outbox.
+#[derive(Debug)]
+pub struct Delivery {
+    id: u64,
+    result: Result<(), String>,
+}
+
+impl Dispatcher {
+    /// Loads the silences and held back messages from their files.
+    /// delivery_sender: where to report the result of each attempt to send a message,
+    /// which should be passed back to [Dispatcher::delivered].
+    pub fn load(config: &AllConfig, delivery_sender: Sender<Delivery>) -> Self {
+        let outbox = outbox::read_outbox(config.get_outbox_path());
+        if !outbox.is_empty() {
+            info!("{} messages in the outbox from before restarting will be sent.", outbox.len());
+        }
+        Self {
+            routes: config.get_routes().clone(),
+            silences: SilenceStore::load(config.get_silences_path().clone()),
+            digest: digest::read_digest(config.get_digest_path()),
+            digest_path: config.get_digest_path().clone(),
+            deferred: quiet_hours::read_deferred(config.get_deferred_path()),
+            deferred_path: config.get_deferred_path().clone(),
+            outbox,
+            outbox_path: config.get_outbox_path().clone(),
+            delivery_sender,
+        }
+    }
+
+    pub fn dispatch(&mut self, notification: Notification, job_config: &Config) {
+        let id = notification.get_job_id().clone();
+        let now = Local::now();
+        let definition = job_config.entries().get(&id);
+        let tags = definition.map(|d| d.get_tags().as_slice()).unwrap_or_default();
+
+        if self.silences.get_mut().suppress_if_silenced(&notification, tags, job_config.get_maintenance_windows(), &now) {
+            info!("[{id}] Notification suppressed by a silence.");
+            self.silences.save();
+            return;
+        }
+
+        let routes = definition.map(|d| d.get_notify_definition().get_routes())
+            .unwrap_or_else(|| vec![String::from(DEFAULT_ROUTE)]);
+        let job_digest = definition.and_then(|d| d.get_notify_definition().get_digest());
+        if let Some(digest_definition) = job_config.get_digest() {
+            if digest_definition.includes(&notification, job_digest) {
+                info!("[{id}] Notification held back for the digest.");
+                self.digest.add(&notification, routes, &now);
+                self.save_digest();
+                return;
+            }
+        }
+
+        let quiet_hours = definition.and_then(|d| job_config.get_quiet_hours(d));
+        if let Some(until) = quiet_hours.and_then(|q| q.defer_until(&notification, &now)) {
+            info!("[{id}] Notification deferred until quiet hours end.");
+            self.deferred.add(&notification, routes, until, &now);
+            self.save_deferred();
+            return;
+        }
+
+        let message = notification.into_message();
+        for route in routes {
+            self.send_via(id.to_string(), route, message.clone());
+        }
+    }
+
+    /// Sends anything that is due, such as the digest, notifications deferred by quiet hours,
+    /// the summary of an ended silence, or retries of messages that failed to send.
+    /// Gives how long until something will next be due, if known.
+    pub fn tick(&mut self, job_config: &Config, now: &DateTime<Local>) -> Option<Duration> {
+        let mut ended = self.silences.reload_if_changed();
+        let expired = self.silences.get_mut().take_ended(job_config.get_maintenance_windows(), now);
+        if !expired.is_empty() {
+            self.silences.save();
+        }
+        ended.extend(expired);
+        let author = job_config.get_daemon_author();
+        for silence in ended {
+            self.send_silence_summary(silence, &author);
+        }
+
+        let timestamp_now = now.timestamp() as u64;
+        let due = self.deferred.take_due(timestamp_now, &author);
+        if !due.is_empty() {
+            for (route, message) in due {
+                self.send_via(String::from("quiet hours"), route, message);
+            }
+            self.save_deferred();
+        }
+        let deferred_wait = self.deferred.next_due()
+            .map(|due| Duration::from_secs(due.saturating_sub(timestamp_now)));
+
+        let digest_wait = self.tick_digest(job_config, now);
+        let outbox_wait = self.tick_outbox(job_config, timestamp_now);
+        [deferred_wait, digest_wait, outbox_wait].into_iter().flatten().min()
+    }
+
+    fn tick_digest(&mut self, job_config: &Config, now: &DateTime<Local>) -> Option<Duration> {
+        let digest_definition = job_config.get_digest()?;
+        let timestamp_now = now.timestamp() as u64;
+        let next_send = match self.digest.get_next_send() {
+            Some(next_send) if timestamp_now < next_send => next_send,
+            Some(_) => {
+                for (route, message) in self.digest.take_messages(&job_config.get_daemon_author()) {
+                    self.send_via(String::from("digest"), route, message);
+                }
+                self.schedule_digest(digest_definition.next_send(now))
+            }
+            None => self.schedule_digest(digest_definition.next_send(now)),
+        };
+        Some(Duration::from_secs(next_send.saturating_sub(timestamp_now)))
+    }
+
+    fn tick_outbox(&mut self, job_config: &Config, timestamp_now: u64) -> Option<Duration> {
+        let expired = self.outbox.take_expired(timestamp_now, job_config.get_outbox());
+        for entry in &expired {
+            let last_error = entry.get_last_error().unwrap_or("none");
+            error!("[{}] Giving up on sending a message to rnotify after {} attempts, last error: {last_error}",
+                entry.get_name(), entry.get_attempts());
+            if !entry.is_fallback_written() {
+                spawn_fallback_write(job_config.get_fallback().clone(), entry.get_name().to_owned(),
+                                     last_error.to_owned(), entry.get_message().clone());
+            }
+        }
+        if !expired.is_empty() {
+            self.save_outbox();
+            self.log_backlog();
+        }
+
+        for id in self.outbox.get_due(timestamp_now) {
+            self.attempt_send(id);
+        }
+        self.outbox.get_next_attempt()
+            .map(|next| Duration::from_secs(next.saturating_sub(timestamp_now)))
+    }
+
+    /// Records the result of trying to send a message, scheduling a retry if it failed.
+    pub fn delivered(&mut self, delivery: Delivery, job_config: &Config) {
+        match delivery.result {
+            Ok(()) => {
+                if let Some(entry) = self.outbox.delivered(delivery.id) {
+                    info!("[{}] Sent a message to rnotify.", entry.get_name());
+                }
+            }
+            Err(err) => {
+                let entry = match self.outbox.get(delivery.id) {
+                    Some(entry) => entry,
+                    None => return,
+                };
+                let name = entry.get_name().to_owned();
+                let message = entry.get_message().clone();
+                // This may only be some of the destinations failing, see FallbackSink.
+                if self.outbox.mark_fallback_written(delivery.id) {
+                    spawn_fallback_write(job_config.get_fallback().clone(), name.clone(), err.clone(), message);
+                }
+                let now = Local::now().timestamp() as u64;
+                if let Some(delay) = self.outbox.failed(delivery.id, err.clone(), now, job_config.get_outbox()) {
+                    warn!("[{name}] Failed to send a message to rnotify, retrying in {}: {err}", format_duration(delay));
+                }
+            }
+        }
+        self.save_outbox();
+        self.log_backlog();
+    }
+
+    /// Uses the rnotify configs from a reloaded config, for messages sent from now on.
+    pub fn set_routes(&mut self, routes: HashMap<String, rnotifylib::config::Config>) {
+        self.routes = routes;
+    }
+
+    /// Adds a silence, such as one given through the control socket.
+    pub fn add_silence(&mut self, silence: Silence) {
+        info!("Silenced {} until {}", silence.get_target().describe(), silence.get_until().format("%Y-%m-%d %H:%M"));
+        self.silences.get_mut().add(silence);
+        self.silences.save();
+    }
+
+    /// Describes the messages being held back or waiting to be sent, one per line.
+    pub fn describe(&self) -> Vec<String> {
+        vec![
+            format!("Outbox backlog: {} messages waiting to be sent", self.outbox.len()),
+            format!("Silences: {}", self.silences.get().get_silences().len()),
+            format!("Held back for the digest: {}", self.digest.get_entries().len()),
+            format!("Deferred by quiet hours: {}", self.deferred.len()),
+        ]
+    }
+
+    fn send(&mut self, name: String, message: Message) {
+        self.send_via(name, String::from(DEFAULT_ROUTE), message);
+    }
+
+    fn send_via(&mut self, name: String, route: String, message: Message) {
+        let now = Local::now().timestamp() as u64;
+        let id = self.outbox.push(name, route, message, now);
+        self.save_outbox();
+        self.attempt_send(id);
+    }
+
+    fn attempt_send(&mut self, id: u64) {
+        let entry = match self.outbox.start_attempt(id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let message = entry.get_message().clone();
+        let rnotify_config = self.routes.get(entry.get_route()).cloned();
+        let route = entry.get_route().to_owned();
+        let delivery_sender = self.delivery_sender.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = match rnotify_config {
+                Some(rnotify_config) => rnotifylib::send_message(message, &rnotify_config)
+                    .map_err(|errs| errs.to_string()),
+                None => Err(format!("Unknown route '{route}'")),
+            };
+            if let Err(err) = delivery_sender.blocking_send(Delivery { id, result }) {
+                error!("Failed to record the result of sending a message: {err}");
+            }
+        });
+    }
+
+    fn log_backlog(&self) {
+        if !self.outbox.is_empty() {
+            info!("Outbox backlog: {} messages waiting to be sent.", self.outbox.len());
+        }
+    }
+
+    fn schedule_digest(&mut self, next_send: u64) -> u64 {
+        debug!("Next digest will be sent at {next_send}");
+        self.digest.set_next_send(next_send);
+        self.save_digest();
+        next_send
+    }
+
+    fn save_digest(&self) {
+        if let Err(err) = std::fs::write(&self.digest_path, self.digest.write_to_string()) {
+            error!("Error writing digest: {err}");
+        }
+    }
+
+    fn save_deferred(&self) {
+        if let Err(err) = std::fs::write(&self.deferred_path, self.deferred.write_to_string()) {
+            error!("Error writing deferred notifications: {err}");
+        }
+    }
+
+    fn save_outbox(&self) {
+        if let Err(err) = std::fs::write(&self.outbox_path, self.outbox.write_to_string()) {
+            error!("Error writing outbox: {err}");
+        }
+    }
+
+    fn send_silence_summary(&mut self, silence: EndedSilence, author: &Author) {
+        info!("{} ended.", silence.get_description());
+        if let Some(message) = silence.create_summary_message(author) {
+            self.send(String::from("silences"), message);
+        }
+    }
+}
+
+fn spawn_fallback_write(sinks: Vec<FallbackSink>, name: String, error: String, message: Message) {
+    if sinks.is_empty() {
+        return;
+    }
+    tokio::task::spawn_blocking(move || {
+        for sink in sinks {
+            match sink.write(&name, &error, &message) {
+                Ok(()) => info!("[{name}] Wrote message that failed to send to fallback {:?}", sink),
+                Err(err) => error!("[{name}] Failed to write message to fallback: {err}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,76 @@
+/// Formats a number of seconds as a short human readable duration,
+/// using the two most significant units, e.g. "3h12m", "1d4h", "45s".
+pub fn format_duration(seconds: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    let days = seconds / DAY;
+    let hours = (seconds % DAY) / HOUR;
+    let minutes = (seconds % HOUR) / MINUTE;
+    let secs = seconds % MINUTE;
+
+    if days > 0 {
+        return format!("{days}d{hours}h");
+    }
+    if hours > 0 {
+        return format!("{hours}h{minutes}m");
+    }
+    if minutes > 0 {
+        return format!("{minutes}m{secs}s");
+    }
+    format!("{secs}s")
+}
+
+/// Parses a duration written like [format_duration] gives, e.g. "3h12m", "1d", "45s" or "90m".
+/// A number without a unit is in seconds.
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(String::from("Empty duration"));
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("Invalid duration '{s}', unknown unit '{c}'")),
+        };
+        let value: u64 = number.parse().map_err(|_| format!("Invalid duration '{s}', expected a number before '{c}'"))?;
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total += number.parse::<u64>().map_err(|err| format!("Invalid duration '{s}': {err}"))?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(5 * 60 + 3), "5m3s");
+        assert_eq!(format_duration(3 * 60 * 60 + 12 * 60 + 59), "3h12m");
+        assert_eq!(format_duration(26 * 60 * 60), "1d2h");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Ok(42), parse_duration("42"));
+        assert_eq!(Ok(3 * 60 * 60 + 12 * 60), parse_duration("3h12m"));
+        assert_eq!(Ok(26 * 60 * 60), parse_duration(&format_duration(26 * 60 * 60)));
+        assert!(parse_duration("2w").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use chrono::{Local, SecondsFormat};
+use rnotifylib::message::{Level, Message};
+use serde::{Serialize, Deserialize};
+
+/// Somewhere local to record messages that could not be sent to rnotify,
+/// so that they leave a trace even if every destination is failing.
+/// A message is written the first time sending it fails, or when it expires from the outbox, whichever is first.
+/// rnotify doesn't report which destinations failed, so a message is written if any one of them fails,
+/// even if the others received it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type")]
+pub enum FallbackSink {
+    /// Appends each message to a file as a line of JSON.
+    JsonLines { path: PathBuf },
+    /// Sends each message to the local syslog socket.
+    Syslog,
+    /// Broadcasts each message to the terminals of logged in users, using `wall`.
+    Wall,
+}
+
+#[derive(Serialize)]
+struct FallbackRecord<'a> {
+    time: String,
+    name: &'a str,
+    error: &'a str,
+    message: &'a Message,
+}
+
+impl FallbackSink {
+    /// Records a message which failed to send.
+    /// name: what the message is about, e.g. the job id, and error: why it couldn't be sent.
+    pub fn write(&self, name: &str, error: &str, message: &Message) -> Result<(), String> {
+        match self {
+            FallbackSink::JsonLines { path } => {
+                let record = FallbackRecord {
+                    time: Local::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                    name,
+                    error,
+                    message,
+                };
+                let mut line = serde_json::to_string(&record).map_err(|err| format!("Failed to serialize message: {err}"))?;
+                line.push('\n');
+                OpenOptions::new().create(true).append(true).open(path)
+                    .and_then(|mut file| file.write_all(line.as_bytes()))
+                    .map_err(|err| format!("Failed to write to {path:?}: {err}"))
+            }
+            FallbackSink::Syslog => write_syslog(message.get_level(), &describe(name, error, message)),
+            FallbackSink::Wall => write_wall(&describe(name, error, message)),
+        }
+    }
+}
+
+fn describe(name: &str, error: &str, message: &Message) -> String {
+    let title = message.get_title().as_deref().unwrap_or("(no title)");
+    format!("[{name}] {:?}: {title} (could not be sent to rnotify: {error})", message.get_level())
+}
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const SYSLOG_FACILITY_USER: u8 = 1;
+
+#[cfg(unix)]
+fn write_syslog(level: &Level, text: &str) -> Result<(), String> {
+    use std::os::unix::net::UnixDatagram;
+
+    let severity = match level {
+        Level::Error | Level::SelfError => 3,
+        Level::Warn => 4,
+        Level::Info | Level::SelfInfo => 6,
+    };
+    let line = format!("<{}>rnotifyd[{}]: {text}", SYSLOG_FACILITY_USER * 8 + severity, std::process::id());
+    UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to(line.as_bytes(), SYSLOG_SOCKET))
+        .map(|_| ())
+        .map_err(|err| format!("Failed to write to syslog: {err}"))
+}
+
+#[cfg(not(unix))]
+fn write_syslog(_level: &Level, _text: &str) -> Result<(), String> {
+    Err(String::from("Syslog is only supported on unix"))
+}
+
+fn write_wall(text: &str) -> Result<(), String> {
+    let mut child = Command::new("wall")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("Failed to run wall: {err}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).map_err(|err| format!("Failed to write to wall: {err}"))?;
+    }
+    let status = child.wait().map_err(|err| format!("Failed to run wall: {err}"))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("wall exited with {status}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rnotifylib::message::author::Author;
+    use rnotifylib::message::MessageDetail;
+    use super::*;
+
+    #[test]
+    fn test_json_lines() {
+        let path = std::env::temp_dir().join(format!("rnotifyd-fallback-test-{}.jsonl", std::process::id()));
+        let sink: FallbackSink = serde_yaml::from_str(&format!("type: JsonLines\npath: {}", path.display())).unwrap();
+        let message = Message::new(Level::Error, Some("Backup failed".to_owned()), MessageDetail::Raw("exit 1".to_owned()),
+                                   None, Author::parse("test".to_owned()), 0);
+
+        sink.write("backup-nightly", "connection refused", &message).unwrap();
+        sink.write("backup-nightly", "connection refused", &message).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(2, lines.len());
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!("backup-nightly", record["name"]);
+        assert_eq!("connection refused", record["error"]);
+    }
+}
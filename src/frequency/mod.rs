@@ -124,6 +124,25 @@ impl Frequency {
             },
         }
     }
+
+    /// Gets the first scheduled time after the given unix time.
+    /// Unlike [Frequency::next], this never gives a time earlier on the same day,
+    /// and fixed periods are counted from the given time.
+    pub fn next_after(&self, after: u64) -> u64 {
+        if let Frequency::FixedPeriod(period) = self {
+            return after + period.as_seconds().max(1);
+        }
+        let from = Local.timestamp_opt(after as i64 + 1, 0).single().unwrap_or_else(Local::now);
+        let next = self.next(&from, None);
+        if next > after {
+            return next;
+        }
+        // Weekly can give a time earlier on the same day, so look again from the next day.
+        let tomorrow = from.date_naive().and_hms_opt(0, 0, 0)
+            .and_then(|midnight| Local.from_local_datetime(&(midnight + Duration::days(1))).earliest())
+            .unwrap_or(from + Duration::days(1));
+        self.next(&tomorrow, None)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -0,0 +1,14 @@
+use std::sync::OnceLock;
+use log::warn;
+
+/// Gets the name of the host rnotifyd is running on, which is looked up the first time it is needed.
+pub fn get_hostname() -> String {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| match hostname::get() {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(err) => {
+            warn!("Failed to get hostname: {err}");
+            String::from("unknown")
+        }
+    }).clone()
+}
@@ -0,0 +1,311 @@
+use serde::{Serialize, Deserialize};
+use crate::config::JobDefinitionId;
+use crate::passive::PingKind;
+
+/// The most that will be read of the body of a request.
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// The local HTTP listener, which passive jobs are pinged through, and jobs can be triggered through.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct HttpDefinition {
+    /// The address to listen on, such as 127.0.0.1:8075
+    #[serde(default = "default_listen")]
+    listen: String,
+    /// The bearer token needed to trigger jobs. If not set, jobs can't be triggered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    /// The bearer token needed to ping passive jobs. If not set, pings don't need a token,
+    /// so it should be set if the listener can be reached from other hosts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ping_token: Option<String>,
+}
+
+impl HttpDefinition {
+    pub fn new(listen: String) -> Self {
+        Self {
+            listen,
+            token: None,
+            ping_token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    pub fn with_ping_token(mut self, ping_token: String) -> Self {
+        self.ping_token = Some(ping_token);
+        self
+    }
+
+    pub fn has_ping_token(&self) -> bool {
+        self.ping_token.is_some()
+    }
+
+    pub fn get_listen(&self) -> &str {
+        &self.listen
+    }
+
+    /// Checks the Authorization header of a request which needs the token.
+    pub fn authorize(&self, authorization: Option<&str>) -> Result<(), Response> {
+        let token = self.token.as_ref()
+            .ok_or_else(|| Response::not_found("Triggering jobs is disabled, as no token is configured"))?;
+        check_bearer_token(token, authorization)
+    }
+
+    /// Checks the Authorization header of a ping, if a ping token is configured.
+    pub fn authorize_ping(&self, authorization: Option<&str>) -> Result<(), Response> {
+        match &self.ping_token {
+            Some(token) => check_bearer_token(token, authorization),
+            None => Ok(()),
+        }
+    }
+}
+
+fn check_bearer_token(token: &str, authorization: Option<&str>) -> Result<(), Response> {
+    let given = authorization.and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or_else(|| Response::unauthorized("Missing bearer token"))?;
+    if !constant_time_eq(given.trim().as_bytes(), token.as_bytes()) {
+        return Err(Response::unauthorized("Invalid token"));
+    }
+    Ok(())
+}
+
+/// Compares the strings in a time that doesn't depend on how much of them match.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn default_listen() -> String {
+    String::from("127.0.0.1:8075")
+}
+
+/// The request line and headers of a HTTP request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestHead {
+    method: String,
+    path: String,
+    /// Header names are lowercase.
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Parses the request line and headers, without the blank line that ends them.
+    pub fn parse(head: &str) -> Result<Self, String> {
+        let mut lines = head.lines();
+        let request_line = lines.next().ok_or("Empty request")?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().ok_or("Missing method")?.to_owned();
+        let path = parts.next().ok_or("Missing path")?.to_owned();
+        let headers = lines.filter(|line| !line.is_empty())
+            .map(|line| {
+                let (name, value) = line.split_once(':').ok_or_else(|| format!("Invalid header '{line}'"))?;
+                Ok((name.trim().to_lowercase(), value.trim().to_owned()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self {
+            method,
+            path,
+            headers,
+        })
+    }
+
+    pub fn get_method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_content_length(&self) -> Result<usize, String> {
+        match self.get_header("content-length") {
+            None => Ok(0),
+            Some(length) => length.parse().map_err(|err| format!("Invalid Content-Length '{length}': {err}")),
+        }
+    }
+}
+
+/// What a request to the HTTP listener asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    Ping { job: JobDefinitionId, kind: PingKind },
+    /// POST /jobs/job-id/run, responding once the run has finished if wait is set, e.g. ?wait=true
+    RunJob { job: JobDefinitionId, wait: bool },
+    /// GET /runs/run-id
+    GetRun { id: u64 },
+}
+
+impl Route {
+    pub fn parse(method: &str, path: &str) -> Result<Self, Response> {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let mut segments = path.trim_matches('/').split('/');
+        match segments.next() {
+            Some("jobs") => {
+                let job = segments.next()
+                    .and_then(|id| JobDefinitionId::try_new(id.to_owned()).ok())
+                    .ok_or_else(|| Response::not_found("Invalid job id"))?;
+                if segments.next() != Some("run") || segments.next().is_some() {
+                    return Err(Response::not_found("Not found, expected /jobs/<job-id>/run"));
+                }
+                if method != "POST" {
+                    return Err(Response::method_not_allowed());
+                }
+                let wait = query.split('&').any(|param| matches!(param, "wait" | "wait=true" | "wait=1"));
+                Ok(Route::RunJob { job, wait })
+            }
+            Some("runs") => {
+                let id = segments.next()
+                    .and_then(|id| id.parse().ok())
+                    .filter(|_| segments.next().is_none())
+                    .ok_or_else(|| Response::not_found("Invalid run id"))?;
+                if method != "GET" {
+                    return Err(Response::method_not_allowed());
+                }
+                Ok(Route::GetRun { id })
+            }
+            Some("ping") => {
+                let job = segments.next()
+                    .and_then(|id| JobDefinitionId::try_new(id.to_owned()).ok())
+                    .ok_or_else(|| Response::not_found("Invalid job id"))?;
+                let kind = PingKind::parse(segments.next())
+                    .filter(|_| segments.next().is_none())
+                    .ok_or_else(|| Response::not_found("Unknown ping, expected /ping/<job-id>, /ping/<job-id>/start or /ping/<job-id>/fail"))?;
+                if method != "POST" {
+                    return Err(Response::method_not_allowed());
+                }
+                Ok(Route::Ping { job, kind })
+            }
+            _ => Err(Response::not_found("Not found")),
+        }
+    }
+
+    /// Whether the request needs the token, see [HttpDefinition::authorize],
+    /// rather than the ping token, see [HttpDefinition::authorize_ping]
+    pub fn requires_token(&self) -> bool {
+        !matches!(self, Route::Ping { .. })
+    }
+}
+
+/// A response to a HTTP request, which is plain text unless created by [Response::json]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(err) => Self::internal_error(&format!("Failed to serialize response: {err}")),
+        }
+    }
+
+    pub fn ok(body: &str) -> Self {
+        Self::new(200, format!("{body}\n"))
+    }
+
+    pub fn bad_request(body: &str) -> Self {
+        Self::new(400, format!("{body}\n"))
+    }
+
+    pub fn unauthorized(body: &str) -> Self {
+        Self::new(401, format!("{body}\n"))
+    }
+
+    pub fn not_found(body: &str) -> Self {
+        Self::new(404, format!("{body}\n"))
+    }
+
+    pub fn too_many_requests(body: &str) -> Self {
+        Self::new(429, format!("{body}\n"))
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Self::new(405, String::from("Method not allowed\n"))
+    }
+
+    pub fn internal_error(body: &str) -> Self {
+        Self::new(500, format!("{body}\n"))
+    }
+
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            429 => "Too Many Requests",
+            _ => "Internal Server Error",
+        };
+        format!("HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                self.status, self.content_type, self.body.len(), self.body).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let head = RequestHead::parse("POST /ping/nightly-export/fail HTTP/1.1\r\nHost: localhost\r\nContent-Length: 12").unwrap();
+        assert_eq!(Ok(12), head.get_content_length());
+
+        let job = JobDefinitionId::try_new("nightly-export".to_owned()).unwrap();
+        assert_eq!(Ok(Route::Ping { job: job.clone(), kind: PingKind::Fail }), Route::parse(head.get_method(), head.get_path()));
+        assert_eq!(Ok(Route::Ping { job, kind: PingKind::Success }), Route::parse("POST", "/ping/nightly-export?x=1"));
+        assert_eq!(Err(Response::method_not_allowed()), Route::parse("GET", "/ping/nightly-export/start"));
+        assert_eq!(404, Route::parse("POST", "/ping/nightly-export/stop").unwrap_err().get_status());
+
+        let job = JobDefinitionId::try_new("smoke-check".to_owned()).unwrap();
+        assert_eq!(Ok(Route::RunJob { job: job.clone(), wait: true }), Route::parse("POST", "/jobs/smoke-check/run?wait=true"));
+        assert_eq!(Ok(Route::RunJob { job, wait: false }), Route::parse("POST", "/jobs/smoke-check/run"));
+        assert_eq!(Ok(Route::GetRun { id: 12 }), Route::parse("GET", "/runs/12"));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let http = HttpDefinition::new(default_listen());
+        assert_eq!(404, http.authorize(Some("Bearer secret")).unwrap_err().get_status());
+
+        let http = http.with_token("secret".to_owned());
+        assert_eq!(Ok(()), http.authorize(Some("Bearer secret")));
+        assert_eq!(401, http.authorize(Some("Bearer secrets")).unwrap_err().get_status());
+        assert_eq!(401, http.authorize(None).unwrap_err().get_status());
+        assert_eq!(Ok(()), http.authorize_ping(None));
+
+        let http = http.with_ping_token("ping-secret".to_owned());
+        assert_eq!(Ok(()), http.authorize_ping(Some("Bearer ping-secret")));
+        assert_eq!(401, http.authorize_ping(Some("Bearer secret")).unwrap_err().get_status());
+        assert_eq!(401, http.authorize_ping(None).unwrap_err().get_status());
+        assert_eq!(401, http.authorize(Some("Bearer ping-secret")).unwrap_err().get_status());
+    }
+}
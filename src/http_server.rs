@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use rnotifydlib::http;
+use rnotifydlib::http::{HttpDefinition, RequestHead, Response, Route};
+
+/// The most that will be read of the request line and headers.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a request waits for a triggered run to finish, before being given the run as it is.
+const WAIT_TIMEOUT: u64 = 60;
+
+/// A request to the HTTP listener, which is handled by the main loop.
+#[derive(Debug)]
+pub struct HttpRequest {
+    route: Route,
+    body: String,
+    reply: oneshot::Sender<Response>,
+}
+
+impl HttpRequest {
+    pub fn get_route(&self) -> &Route {
+        &self.route
+    }
+
+    pub fn get_body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn respond(self, response: Response) {
+        if self.reply.send(response).is_err() {
+            debug!("Client disconnected before it was responded to.");
+        }
+    }
+}
+
+/// Requests waiting for triggered runs to finish, by run id.
+#[derive(Debug, Default)]
+pub struct WaitingRequests {
+    /// The requests for each run, with when they stop waiting.
+    requests: HashMap<u64, Vec<(HttpRequest, u64)>>,
+}
+
+impl WaitingRequests {
+    pub fn add(&mut self, run_id: u64, request: HttpRequest, now: u64) {
+        self.requests.entry(run_id).or_default().push((request, now + WAIT_TIMEOUT));
+    }
+
+    /// Removes the requests waiting for the run, such as once it has finished.
+    pub fn take(&mut self, run_id: u64) -> Vec<HttpRequest> {
+        self.requests.remove(&run_id).unwrap_or_default()
+            .into_iter()
+            .map(|(request, _)| request)
+            .collect()
+    }
+
+    /// Removes the requests which have waited too long, with the id of the run each is waiting for.
+    pub fn take_timed_out(&mut self, now: u64) -> Vec<(u64, HttpRequest)> {
+        let mut timed_out = vec![];
+        for (run_id, requests) in self.requests.iter_mut() {
+            let (expired, kept): (Vec<_>, Vec<_>) = std::mem::take(requests).into_iter()
+                .partition(|(_, until)| *until <= now);
+            *requests = kept;
+            timed_out.extend(expired.into_iter().map(|(request, _)| (*run_id, request)));
+        }
+        self.requests.retain(|_, requests| !requests.is_empty());
+        timed_out
+    }
+
+    /// Gets the soonest time a request stops waiting.
+    pub fn get_next_timeout(&self) -> Option<u64> {
+        self.requests.values().flatten().map(|(_, until)| *until).min()
+    }
+}
+
+/// Starts listening for HTTP requests, which are passed to the sender.
+pub fn spawn_server(definition: &HttpDefinition, sender: Sender<HttpRequest>) {
+    let definition = definition.clone();
+    let listen = definition.get_listen().to_owned();
+    if !definition.has_ping_token() && !is_loopback(&listen) {
+        warn!("The HTTP listener on {listen} can be reached from other hosts, but no ping_token is set, \
+               so anyone who can reach it can ping passive jobs.");
+    }
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(&listen).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to listen for HTTP requests on {listen}: {err}");
+                return;
+            }
+        };
+        info!("Listening for HTTP requests on {listen}");
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, definition.clone(), sender.clone()));
+                }
+                Err(err) => warn!("Failed to accept HTTP connection: {err}"),
+            }
+        }
+    });
+}
+
+async fn handle_connection(stream: TcpStream, definition: HttpDefinition, sender: Sender<HttpRequest>) {
+    let mut reader = BufReader::new(stream);
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader, &definition)).await {
+        Err(_) => Response::bad_request("Timed out reading request"),
+        Ok(Err(response)) => response,
+        Ok(Ok((route, body))) => {
+            let (reply, response) = oneshot::channel();
+            match sender.send(HttpRequest { route, body, reply }).await {
+                Ok(()) => response.await.unwrap_or_else(|_| Response::internal_error("Request was not handled")),
+                Err(_) => Response::internal_error("Not accepting requests"),
+            }
+        }
+    };
+    let mut stream = reader.into_inner();
+    if let Err(err) = stream.write_all(&response.to_bytes()).await {
+        debug!("Failed to write HTTP response: {err}");
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Whether the address only listens for connections from this host, e.g. 127.0.0.1:8075
+fn is_loopback(listen: &str) -> bool {
+    let host = listen.rsplit_once(':').map_or(listen, |(host, _)| host);
+    match host.trim_matches(|c| c == '[' || c == ']').parse::<std::net::IpAddr>() {
+        Ok(ip) => ip.is_loopback(),
+        Err(_) => host == "localhost",
+    }
+}
+
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R, definition: &HttpDefinition) -> Result<(Route, String), Response> {
+    let mut head = String::new();
+    // Limit the whole head, so that a single long line isn't read in full.
+    let mut limited = (&mut *reader).take(MAX_HEAD_SIZE as u64);
+    loop {
+        let mut line = String::new();
+        let read = limited.read_line(&mut line).await
+            .map_err(|err| Response::bad_request(&format!("Failed to read request: {err}")))?;
+        if limited.limit() == 0 && !line.ends_with('\n') {
+            return Err(Response::bad_request("Request headers too large"));
+        }
+        if read == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    let head = RequestHead::parse(&head).map_err(|err| Response::bad_request(&err))?;
+    let route = Route::parse(head.get_method(), head.get_path())?;
+    match route.requires_token() {
+        true => definition.authorize(head.get_header("authorization"))?,
+        false => definition.authorize_ping(head.get_header("authorization"))?,
+    }
+
+    let length = head.get_content_length().map_err(|err| Response::bad_request(&err))?;
+    if length > http::MAX_BODY_SIZE {
+        return Err(Response::bad_request("Request body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await
+        .map_err(|err| Response::bad_request(&format!("Failed to read request body: {err}")))?;
+    Ok((route, String::from_utf8_lossy(&body).into_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(request: &[u8], definition: &HttpDefinition) -> Result<(Route, String), Response> {
+        let mut reader = request;
+        futures::executor::block_on(read_request(&mut reader, definition))
+    }
+
+    #[test]
+    fn test_read_request() {
+        let definition = HttpDefinition::new(String::from("127.0.0.1:8075"));
+        let (route, body) = read(b"POST /ping/backup HTTP/1.1\r\nContent-Length: 2\r\n\r\nok", &definition).unwrap();
+        assert!(matches!(route, Route::Ping { .. }));
+        assert_eq!("ok", body);
+
+        let long_header = format!("POST /ping/backup HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+        assert_eq!(400, read(long_header.as_bytes(), &definition).unwrap_err().get_status());
+
+        let definition = definition.with_ping_token(String::from("secret"));
+        assert_eq!(401, read(b"POST /ping/backup HTTP/1.1\r\n\r\n", &definition).unwrap_err().get_status());
+        assert!(read(b"POST /ping/backup HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n", &definition).is_ok());
+    }
+
+    #[test]
+    fn test_waiting_requests() {
+        let request = || {
+            let route = Route::GetRun { id: 1 };
+            let (reply, _) = oneshot::channel();
+            HttpRequest { route, body: String::new(), reply }
+        };
+        let mut waiting = WaitingRequests::default();
+        waiting.add(1, request(), 100);
+        waiting.add(2, request(), 130);
+        assert_eq!(Some(100 + WAIT_TIMEOUT), waiting.get_next_timeout());
+
+        assert!(waiting.take_timed_out(100 + WAIT_TIMEOUT - 1).is_empty());
+        let timed_out = waiting.take_timed_out(100 + WAIT_TIMEOUT);
+        assert_eq!(vec![1], timed_out.iter().map(|(run_id, _)| *run_id).collect::<Vec<_>>());
+        assert_eq!(Some(130 + WAIT_TIMEOUT), waiting.get_next_timeout());
+
+        assert_eq!(1, waiting.take(2).len());
+        assert_eq!(None, waiting.get_next_timeout());
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback("127.0.0.1:8075"));
+        assert!(is_loopback("[::1]:8075"));
+        assert!(is_loopback("localhost:8075"));
+        assert!(!is_loopback("0.0.0.0:8075"));
+        assert!(!is_loopback("192.168.0.2:8075"));
+    }
+}
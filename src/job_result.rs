@@ -1,4 +1,7 @@
 use rnotifylib::message::{MessageDetail};
+use crate::job_state::ResultKind;
+use crate::metrics::Metrics;
+use crate::template::TemplateContext;
 
 pub enum JobResult {
     /// The job successfully ran
@@ -7,14 +10,26 @@ pub enum JobResult {
     Invalid(MessageDetail),
     /// The job ran, and detected a problem.
     Failed(MessageDetail),
+    /// The job ran successfully, but a metric it reported exceeded its warning threshold.
+    Warning(MessageDetail),
 }
 
 impl JobResult {
+    pub fn new(kind: ResultKind, detail: MessageDetail) -> Self {
+        match kind {
+            ResultKind::Ok => JobResult::Ok(detail),
+            ResultKind::Invalid => JobResult::Invalid(detail),
+            ResultKind::Failed => JobResult::Failed(detail),
+            ResultKind::Warning => JobResult::Warning(detail),
+        }
+    }
+
     pub fn take_detail(self) -> MessageDetail {
         match self {
             JobResult::Ok(detail) => detail,
             JobResult::Invalid(detail) => detail,
             JobResult::Failed(detail) => detail,
+            JobResult::Warning(detail) => detail,
         }
     }
 
@@ -23,6 +38,88 @@ impl JobResult {
             JobResult::Ok(_) => "ok",
             JobResult::Invalid(_) => "invalid",
             JobResult::Failed(_) => "failed",
+            JobResult::Warning(_) => "warning",
         }
     }
-}
\ No newline at end of file
+
+    pub fn kind(&self) -> ResultKind {
+        match self {
+            JobResult::Ok(_) => ResultKind::Ok,
+            JobResult::Invalid(_) => ResultKind::Invalid,
+            JobResult::Failed(_) => ResultKind::Failed,
+            JobResult::Warning(_) => ResultKind::Warning,
+        }
+    }
+}
+
+/// The result of a job, along with what it reported.
+pub struct JobOutcome {
+    result: JobResult,
+    /// The title of the notification, rendered from the title template.
+    title: String,
+    metrics: Metrics,
+    /// The stdout of the job, if it should be remembered for comparing against the next run.
+    stdout: Option<String>,
+    /// Whether the stdout differs from the previous run's.
+    output_changed: bool,
+    /// The variables the title was rendered with, for rendering other templates.
+    context: TemplateContext,
+    /// How long the job took to run, in seconds.
+    duration: u64,
+}
+
+impl JobOutcome {
+    pub fn new(result: JobResult, title: String, metrics: Metrics, stdout: Option<String>, output_changed: bool) -> Self {
+        Self {
+            result,
+            title,
+            metrics,
+            stdout,
+            output_changed,
+            context: TemplateContext::new(),
+            duration: 0,
+        }
+    }
+
+    pub fn with_duration(mut self, duration: u64) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn get_duration(&self) -> u64 {
+        self.duration
+    }
+
+    pub fn with_context(mut self, context: TemplateContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn get_context(&self) -> &TemplateContext {
+        &self.context
+    }
+
+    pub fn get_result(&self) -> &JobResult {
+        &self.result
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn take_stdout(&mut self) -> Option<String> {
+        self.stdout.take()
+    }
+
+    pub fn is_output_changed(&self) -> bool {
+        self.output_changed
+    }
+
+    pub fn into_parts(self) -> (JobResult, String) {
+        (self.result, self.title)
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use log::warn;
+use serde::{Serialize, Deserialize};
+use crate::alert::Alert;
+use crate::config::JobDefinitionId;
+use crate::deadline::PendingDeadline;
+use crate::metrics::Metrics;
+use crate::pause::JobPause;
+
+/// The maximum number of runs kept in the history of each job.
+const MAX_HISTORY: usize = 50;
+
+/// Persistent state about each job, kept across runs and restarts.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct JobStates {
+    #[serde(default)]
+    jobs: HashMap<JobDefinitionId, JobState>,
+}
+
+impl JobStates {
+    pub fn get(&self, id: &JobDefinitionId) -> Option<&JobState> {
+        self.jobs.get(id)
+    }
+
+    /// Gets a copy of the state of the given job, or an empty state if the job has never run.
+    pub fn get_or_default(&self, id: &JobDefinitionId) -> JobState {
+        self.jobs.get(id).cloned().unwrap_or_default()
+    }
+
+    pub fn record(&mut self, id: JobDefinitionId, record: RunRecord) {
+        self.jobs.entry(id).or_default().record(record);
+    }
+
+    pub fn set_alert(&mut self, id: JobDefinitionId, alert: Option<Alert>, flapping: bool) {
+        let state = self.jobs.entry(id).or_default();
+        state.alert = alert;
+        state.flapping = flapping;
+    }
+
+    pub fn set_last_stdout(&mut self, id: JobDefinitionId, stdout: String) {
+        self.jobs.entry(id).or_default().last_stdout = Some(stdout);
+    }
+
+    /// Records that the job was not run at the given scheduled time, because it was still running.
+    pub fn record_skipped(&mut self, id: JobDefinitionId, scheduled: u64) {
+        let skipped = &mut self.jobs.entry(id).or_default().skipped;
+        skipped.push(scheduled);
+        if skipped.len() > MAX_HISTORY {
+            skipped.remove(0);
+        }
+    }
+
+    pub fn set_deadline(&mut self, id: JobDefinitionId, deadline: PendingDeadline) {
+        self.jobs.entry(id).or_default().deadline = Some(deadline);
+    }
+
+    pub fn clear_deadline(&mut self, id: &JobDefinitionId) {
+        if let Some(state) = self.jobs.get_mut(id) {
+            state.deadline = None;
+        }
+    }
+
+    pub fn set_pause(&mut self, id: JobDefinitionId, pause: Option<JobPause>) {
+        self.jobs.entry(id).or_default().pause = pause;
+    }
+
+    pub fn is_paused(&self, id: &JobDefinitionId) -> bool {
+        self.jobs.get(id).is_some_and(|state| state.is_paused())
+    }
+
+    /// Resumes the jobs whose pause has ended, giving their ids.
+    pub fn take_ended_pauses(&mut self, now: u64) -> Vec<JobDefinitionId> {
+        self.jobs.iter_mut()
+            .filter(|(_, state)| state.pause.is_some_and(|pause| pause.has_ended(now)))
+            .map(|(id, state)| {
+                state.pause = None;
+                id.clone()
+            })
+            .collect()
+    }
+
+    /// Gets the soonest time a paused job is due to be resumed.
+    pub fn get_next_pause_end(&self) -> Option<u64> {
+        self.jobs.values()
+            .filter_map(|state| state.pause.and_then(|pause| pause.get_until()))
+            .min()
+    }
+
+    pub fn read_from_string(s: &str) -> Result<JobStates, String> {
+        serde_yaml::from_str(s).map_err(|err| format!("Error parsing job state: {err}"))
+    }
+
+    pub fn write_to_string(&self) -> String {
+        serde_yaml::to_string(self).expect("Failed to serialize job state")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct JobState {
+    /// Most recent runs of the job, oldest first.
+    #[serde(default)]
+    history: Vec<RunRecord>,
+    /// The stdout of the last successful run, for jobs which compare their output between runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_stdout: Option<String>,
+    #[serde(default)]
+    consecutive_successes: u32,
+    #[serde(default)]
+    consecutive_failures: u32,
+    /// When the most recent successful run finished, which is kept even once the run is no longer in the history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_success_finished: Option<u64>,
+    /// When the first run in the current streak of unsuccessful runs started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unsuccessful_since: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alert: Option<Alert>,
+    #[serde(default)]
+    flapping: bool,
+    /// Scheduled times at which the job wasn't run because it was still running, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<u64>,
+    /// The next time the job must have succeeded by, for jobs with must_succeed_by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deadline: Option<PendingDeadline>,
+    /// Whether the job has been paused at runtime, so isn't run on its schedule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pause: Option<JobPause>,
+}
+
+impl JobState {
+    pub fn get_history(&self) -> &Vec<RunRecord> {
+        &self.history
+    }
+
+    pub fn get_last_stdout(&self) -> Option<&str> {
+        self.last_stdout.as_deref()
+    }
+
+    pub fn get_skipped(&self) -> &Vec<u64> {
+        &self.skipped
+    }
+
+    /// Gets the number of scheduled runs skipped since the given time.
+    pub fn count_skipped_since(&self, since: u64) -> u32 {
+        self.skipped.iter().filter(|time| **time >= since).count() as u32
+    }
+
+    pub fn get_last_run(&self) -> Option<&RunRecord> {
+        self.history.last()
+    }
+
+    /// Gets the start time of the most recent successful run that is still in the history.
+    pub fn get_last_success(&self) -> Option<u64> {
+        self.history.iter().rev()
+            .find(|record| record.result.is_ok())
+            .map(|record| record.started)
+    }
+
+    /// Whether a successful run finished after since, and no later than until.
+    /// Runs with no known duration are treated as finishing when they started.
+    pub fn has_succeeded_between(&self, since: u64, until: u64) -> bool {
+        // The last success covers runs that are no longer in the history,
+        // and the history covers a success in the period followed by another after it.
+        self.last_success_finished.into_iter()
+            .chain(self.history.iter()
+                .filter(|record| record.result.is_ok())
+                .map(|record| record.finished()))
+            .any(|finished| since < finished && finished <= until)
+    }
+
+    pub fn get_deadline(&self) -> Option<PendingDeadline> {
+        self.deadline
+    }
+
+    pub fn get_pause(&self) -> Option<&JobPause> {
+        self.pause.as_ref()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_some()
+    }
+
+    /// Gets the number of runs since the last successful run.
+    pub fn get_consecutive_unsuccessful_runs(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Gets the number of runs since the last unsuccessful run.
+    pub fn get_consecutive_successful_runs(&self) -> u32 {
+        self.consecutive_successes
+    }
+
+    /// Gets the start time of the first run in the current streak of unsuccessful runs,
+    /// or None if the last run was successful.
+    pub fn get_unsuccessful_since(&self) -> Option<u64> {
+        self.unsuccessful_since
+    }
+
+    pub fn get_alert(&self) -> Option<&Alert> {
+        self.alert.as_ref()
+    }
+
+    pub fn is_flapping(&self) -> bool {
+        self.flapping
+    }
+
+    /// Counts the number of times the job changed between success and failure,
+    /// in the runs since the given time, followed by a run with the given result.
+    pub fn count_transitions_since(&self, since: u64, current: ResultKind) -> u32 {
+        let results: Vec<bool> = self.history.iter()
+            .filter(|run| run.started >= since)
+            .map(|run| run.result.is_ok())
+            .chain(std::iter::once(current.is_ok()))
+            .collect();
+        results.windows(2)
+            .filter(|pair| pair[0] != pair[1])
+            .count() as u32
+    }
+
+    /// Gets the most recent recorded value of a metric, and the time of the run that recorded it.
+    pub fn get_previous_metric(&self, name: &str) -> Option<(f64, u64)> {
+        self.history.iter().rev()
+            .find_map(|run| run.metrics.get(name).map(|value| (*value, run.started)))
+    }
+
+    /// Gets the median duration of the successful runs in the history,
+    /// or None if there are fewer than min_runs of them.
+    pub fn get_median_duration(&self, min_runs: usize) -> Option<u64> {
+        let mut durations: Vec<u64> = self.history.iter()
+            .filter(|run| run.result.is_ok())
+            .filter_map(|run| run.duration)
+            .collect();
+        if durations.is_empty() || durations.len() < min_runs {
+            return None;
+        }
+        durations.sort_unstable();
+        Some(durations[durations.len() / 2])
+    }
+
+    fn record(&mut self, record: RunRecord) {
+        if record.result.is_ok() {
+            self.consecutive_successes += 1;
+            self.consecutive_failures = 0;
+            self.unsuccessful_since = None;
+            self.last_success_finished = self.last_success_finished.max(Some(record.finished()));
+        } else {
+            self.consecutive_successes = 0;
+            self.consecutive_failures += 1;
+            self.unsuccessful_since.get_or_insert(record.started);
+        }
+        self.history.push(record);
+        if self.history.len() > MAX_HISTORY {
+            let excess = self.history.len() - MAX_HISTORY;
+            self.history.drain(..excess);
+        }
+    }
+}
+
+/// A summary of a single completed run of a job.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunRecord {
+    started: u64,
+    result: ResultKind,
+    #[serde(default, skip_serializing_if = "Metrics::is_empty")]
+    metrics: Metrics,
+    /// How long the run took in seconds, not known for runs recorded by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<u64>,
+}
+
+impl RunRecord {
+    pub fn new(started: u64, result: ResultKind, metrics: Metrics) -> Self {
+        Self {
+            started,
+            result,
+            metrics,
+            duration: None,
+        }
+    }
+
+    /// When the run finished, or when it started if its duration isn't known.
+    fn finished(&self) -> u64 {
+        self.started + self.duration.unwrap_or(0)
+    }
+
+    pub fn with_duration(mut self, duration: u64) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn get_duration(&self) -> Option<u64> {
+        self.duration
+    }
+
+    pub fn get_started(&self) -> u64 {
+        self.started
+    }
+
+    pub fn get_result(&self) -> ResultKind {
+        self.result
+    }
+
+    pub fn get_metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+/// The type of a [JobResult](crate::job_result::JobResult), without its detail.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultKind {
+    Ok,
+    Warning,
+    Failed,
+    Invalid,
+}
+
+impl ResultKind {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, ResultKind::Ok)
+    }
+
+    pub fn type_str(&self) -> &str {
+        match self {
+            ResultKind::Ok => "ok",
+            ResultKind::Warning => "warning",
+            ResultKind::Failed => "failed",
+            ResultKind::Invalid => "invalid",
+        }
+    }
+}
+
+pub fn read_job_states(path: &PathBuf) -> JobStates {
+    if !path.exists() {
+        warn!("Cannot find job state file, assuming no jobs have any state.");
+        return JobStates::default();
+    }
+    let job_state_str = fs::read_to_string(path).expect("Failed to read job state");
+
+    JobStates::read_from_string(&job_state_str).expect("Failed to parse job state.")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let id = JobDefinitionId::try_new("disk-usage".to_owned()).unwrap();
+        let mut metrics = Metrics::new();
+        metrics.insert("disk_used_pct".to_owned(), 71.0);
+
+        let mut states = JobStates::default();
+        states.record(id.clone(), RunRecord::new(1670340125, ResultKind::Ok, metrics));
+        states.record(id.clone(), RunRecord::new(1670426525, ResultKind::Failed, Metrics::new()).with_duration(12));
+
+        let parsed = JobStates::read_from_string(&states.write_to_string()).unwrap();
+        assert_eq!(states, parsed);
+        assert_eq!(Some((71.0, 1670340125)), parsed.get_or_default(&id).get_previous_metric("disk_used_pct"));
+    }
+
+    #[test]
+    fn test_has_succeeded_between() {
+        let id = JobDefinitionId::try_new("sync".to_owned()).unwrap();
+        let mut states = JobStates::default();
+        states.record(id.clone(), RunRecord::new(100, ResultKind::Ok, Metrics::new()).with_duration(10));
+        assert!(states.get_or_default(&id).has_succeeded_between(0, 110));
+        assert!(!states.get_or_default(&id).has_succeeded_between(0, 109));
+        assert!(!states.get_or_default(&id).has_succeeded_between(110, 1000));
+
+        // The success is still known once it has been pushed out of the history.
+        for started in 0..MAX_HISTORY as u64 {
+            states.record(id.clone(), RunRecord::new(200 + started, ResultKind::Failed, Metrics::new()));
+        }
+        let state = states.get_or_default(&id);
+        assert!(state.get_history().iter().all(|record| !record.get_result().is_ok()));
+        assert!(state.has_succeeded_between(0, 1000));
+
+        // A success in the period still counts after a later success outside it.
+        states.record(id.clone(), RunRecord::new(500, ResultKind::Ok, Metrics::new()));
+        states.record(id.clone(), RunRecord::new(2000, ResultKind::Ok, Metrics::new()));
+        assert!(states.get_or_default(&id).has_succeeded_between(400, 1000));
+    }
+
+    #[test]
+    fn test_count_skipped_since() {
+        let id = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        let mut states = JobStates::default();
+        assert_eq!(0, states.get_or_default(&id).count_skipped_since(0));
+        for scheduled in [100, 200, 300] {
+            states.record_skipped(id.clone(), scheduled);
+        }
+        let state = states.get_or_default(&id);
+        assert_eq!(3, state.count_skipped_since(100));
+        assert_eq!(1, state.count_skipped_since(250));
+        assert_eq!(0, state.count_skipped_since(301));
+    }
+
+    #[test]
+    fn test_take_ended_pauses() {
+        let backup = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        let disk_usage = JobDefinitionId::try_new("disk-usage".to_owned()).unwrap();
+        let mut states = JobStates::default();
+        states.set_pause(backup.clone(), Some(JobPause::new(100, Some(200))));
+        states.set_pause(disk_usage.clone(), Some(JobPause::new(100, None)));
+        assert_eq!(Some(200), states.get_next_pause_end());
+
+        assert!(states.take_ended_pauses(199).is_empty());
+        assert_eq!(vec![backup.clone()], states.take_ended_pauses(200));
+        assert!(!states.get_or_default(&backup).is_paused());
+        assert!(states.get_or_default(&disk_usage).is_paused());
+        assert_eq!(None, states.get_next_pause_end());
+    }
+}
@@ -3,4 +3,23 @@ pub mod frequency;
 pub mod action;
 pub mod notify_definition;
 pub mod program_output;
-pub mod job_result;
\ No newline at end of file
+pub mod job_result;
+pub mod metrics;
+pub mod job_state;
+pub mod duration_format;
+pub mod redact;
+pub mod template;
+pub mod host;
+pub mod alert;
+pub mod notification;
+pub mod silence;
+pub mod digest;
+pub mod quiet_hours;
+pub mod outbox;
+pub mod fallback;
+pub mod deadline;
+pub mod passive;
+pub mod http;
+pub mod trigger;
+pub mod control;
+pub mod pause;
\ No newline at end of file
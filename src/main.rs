@@ -1,5 +1,6 @@
 use std::cmp::min;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use chrono::{Local, SecondsFormat};
 use env_logger::Env;
@@ -10,15 +11,42 @@ use tokio::sync::mpsc::Sender;
 use all_config::AllConfig;
 use next_run::NextRun;
 use rnotifydlib::action;
-use rnotifydlib::config::JobDefinitionId;
-use rnotifydlib::job_result::JobResult;
+use rnotifydlib::alert;
+use rnotifydlib::deadline;
+use rnotifydlib::alert::{AlertEvent, AlertUpdate};
+use rnotifydlib::config::{Config, JobDefinition, JobDefinitionId};
+use rnotifydlib::control::{ControlRequest, ControlResponse};
+use rnotifydlib::duration_format::format_duration;
+use rnotifydlib::http::{Response, Route};
+use rnotifydlib::job_result::{JobOutcome, JobResult};
+use rnotifydlib::job_state;
+use rnotifydlib::job_state::{JobState, JobStates, ResultKind, RunRecord};
 use rnotifydlib::notify_definition::NotifyDefinition;
+use rnotifydlib::notification::Notification;
+use rnotifydlib::passive;
+use rnotifydlib::passive::PingKind;
+use rnotifydlib::pause::JobPause;
+use rnotifydlib::redact::Redactor;
+use rnotifydlib::silence::{Silence, SilenceTarget};
+use rnotifydlib::template::Template;
+use rnotifydlib::trigger::TriggeredRuns;
 use crate::run_log::RunLog;
+use crate::control_server::ControlCommand;
+use crate::dispatch::Dispatcher;
+use crate::http_server::WaitingRequests;
 use crate::running_jobs::RunningJobs;
 
 const RNOTIFY_CONFIG_ARG: &str = "rnotify-config";
 const RNOTIFYD_CONFIG_ARG: &str = "config";
 const RNOTIFY_RUN_LOG_ARG: &str = "runlog";
+const RNOTIFYD_STATE_ARG: &str = "state";
+const RNOTIFYD_SILENCES_ARG: &str = "silences";
+const RNOTIFYD_DIGEST_ARG: &str = "digest";
+const RNOTIFYD_DEFERRED_ARG: &str = "deferred";
+const RNOTIFYD_OUTBOX_ARG: &str = "outbox";
+const RNOTIFYD_SOCKET_ARG: &str = "socket";
+
+const DEFAULT_SOCKET: &str = "rnotifyd.sock";
 
 const CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
@@ -26,8 +54,20 @@ mod run_log;
 mod all_config;
 mod next_run;
 mod running_jobs;
+mod silence_store;
+mod dispatch;
+mod http_server;
+mod control_server;
+#[cfg(unix)]
+mod control_client;
+mod describe;
 
 fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("ctl") {
+        std::process::exit(run_ctl(&args[2..]));
+    }
+
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let runtime = tokio::runtime::Builder::new_multi_thread()
@@ -42,7 +82,12 @@ fn main() {
     opts.optopt("", RNOTIFY_CONFIG_ARG, "The rnotify.toml file.", "RNOTIFY");
     opts.reqopt("", RNOTIFYD_CONFIG_ARG, "The rnotifyd.yaml file.", "RNOTIFYD");
     opts.optopt("", RNOTIFY_RUN_LOG_ARG, "The run log file.", "RUNLOG");
-    let args: Vec<_> = std::env::args().collect();
+    opts.optopt("", RNOTIFYD_STATE_ARG, "The job state file.", "STATE");
+    opts.optopt("", RNOTIFYD_SILENCES_ARG, "The silences file.", "SILENCES");
+    opts.optopt("", RNOTIFYD_DIGEST_ARG, "The file to keep notifications held back for the digest in.", "DIGEST");
+    opts.optopt("", RNOTIFYD_DEFERRED_ARG, "The file to keep notifications deferred by quiet hours in.", "DEFERRED");
+    opts.optopt("", RNOTIFYD_OUTBOX_ARG, "The file to keep messages waiting to be sent to rnotify in.", "OUTBOX");
+    opts.optopt("", RNOTIFYD_SOCKET_ARG, "The control socket to listen for rnotifyd ctl commands on.", "SOCKET");
     let parsed = match opts.parse(args) {
         Ok(matches) => matches,
         Err(err) => panic!("Invalid arguments: {}", err),
@@ -52,17 +97,30 @@ fn main() {
     let run_log = run_log::read_run_log(&configs.get_run_log_path());
     debug!("RunLog: {:?}", run_log);
 
-    futures::executor::block_on(main_loop(configs, run_log, &runtime));
+    let job_states = job_state::read_job_states(configs.get_state_path());
+
+    futures::executor::block_on(main_loop(configs, run_log, job_states, &runtime));
     runtime.shutdown_timeout(Duration::from_millis(250));
     info!("-- Stopped at: {} --", Local::now().to_rfc3339_opts(SecondsFormat::Millis, true))
 }
 
-async fn main_loop(config: AllConfig, mut run_log: RunLog, rt: &Runtime) {
+#[cfg(unix)]
+fn run_ctl(args: &[String]) -> i32 {
+    control_client::run(args)
+}
+
+#[cfg(not(unix))]
+fn run_ctl(_args: &[String]) -> i32 {
+    eprintln!("rnotifyd ctl is only supported on unix.");
+    1
+}
+
+async fn main_loop(mut config: AllConfig, mut run_log: RunLog, mut job_states: JobStates, rt: &Runtime) {
     // Make the current tokio runtime, be this runtime.
     let _guard = rt.enter();
 
     debug!("Beginning main loop.");
-    let job_config = config.get_job_config().clone();
+    let mut job_config = config.get_job_config().clone();
     let mut next_run = NextRun::new();
 
     // Currently running non-parallel allowed jobs
@@ -71,30 +129,139 @@ async fn main_loop(config: AllConfig, mut run_log: RunLog, rt: &Runtime) {
     // Sender to report when jobs finish.
     let (send, mut recv) = tokio::sync::mpsc::channel(10);
 
+    // Sender to report the result of sending messages.
+    let (delivery_send, mut delivery_recv) = tokio::sync::mpsc::channel(10);
+    let mut dispatcher = Dispatcher::load(&config, delivery_send);
+
+    // Sender for requests to the HTTP listener, such as pings from passive jobs.
+    let (http_send, mut http_recv) = tokio::sync::mpsc::channel(10);
+    if let Some(http) = job_config.get_http() {
+        http_server::spawn_server(http, http_send.clone());
+    }
+    // Runs triggered over HTTP, and the requests waiting for them to finish.
+    let mut triggered = TriggeredRuns::default();
+    let mut waiting = WaitingRequests::default();
+
+    // Sender for commands from rnotifyd ctl.
+    let (control_send, mut control_recv) = tokio::sync::mpsc::channel(10);
+    #[cfg(unix)]
+    control_server::spawn_server(config.get_socket_path(), control_send.clone());
+    #[cfg(unix)]
+    control_server::spawn_hangup_listener(control_send.clone());
+    let started = Local::now().timestamp() as u64;
+
     loop {
         let now = Local::now();
         let timestamp_now = now.timestamp() as u64;
+        let dispatch_wait = dispatcher.tick(&job_config, &now);
+
+        // Give up waiting for runs that are taking too long, responding with the run so it can be polled.
+        for (run_id, request) in waiting.take_timed_out(timestamp_now) {
+            request.respond(Response::json(202, &triggered.get(run_id)));
+        }
+
+        let resumed = job_states.take_ended_pauses(timestamp_now);
+        if !resumed.is_empty() {
+            for id in resumed {
+                info!("[{id}] Resumed, as its pause has ended.");
+            }
+            spawn_file_write("job state", job_states.write_to_string(), config.get_state_path().clone());
+        }
+
+        for (run_id, id) in triggered.get_queued() {
+            let definition = match job_config.entries().get(&id) {
+                Some(definition) => definition,
+                None => continue,
+            };
+            if !definition.allow_parallel() && running.any_running(&id) {
+                continue;
+            }
+            debug!("[{id}] Starting triggered run {run_id}.");
+            running.add(id.clone(), timestamp_now);
+            triggered.start(run_id, timestamp_now);
+            let next = next_run.update_and_get(&id, definition.get_frequency(), now, &run_log, &running);
+            let job_run = JobRun::new(&id, definition, &job_config, config.get_redactor(&id), job_states.get_or_default(&id), timestamp_now, next)
+                .with_trigger(run_id);
+            spawn_job(job_run, send.clone());
+        }
+
         for (id, definition) in job_config.entries() {
+            if let Some(passive) = definition.get_passive() {
+                let deadline = next_run.update_and_get_passive(id, definition.get_frequency(), passive, timestamp_now, &run_log);
+                if timestamp_now >= deadline && job_states.is_paused(id) {
+                    debug!("[{id}] Hasn't been pinged, but is paused.");
+                    next_run.skip_passive(id, definition.get_frequency(), passive, timestamp_now);
+                } else if timestamp_now >= deadline {
+                    let expected = deadline.saturating_sub(passive.get_grace());
+                    warn!("[{id}] Hasn't been pinged, {} after it was expected.", format_duration(timestamp_now.saturating_sub(expected)));
+                    let previous = job_states.get_or_default(id);
+                    let outcome = passive::create_missed_outcome(id, definition.get_notify_definition(), &previous, expected,
+                                                                 running.get_earliest(id), timestamp_now);
+                    // Expect the next ping relative to now, so that only one alert is sent if several pings were missed.
+                    let missed_at = expected.max(timestamp_now.saturating_sub(passive.get_grace()));
+                    let job_finish = finish_run(id.clone(), definition.get_notify_definition(), &job_config.get_author(definition),
+                                                &previous, missed_at, outcome);
+                    run_log.record(id.clone(), missed_at);
+                    spawn_file_write("run log", run_log.write_to_string(), config.get_run_log_path().clone());
+                    record_finish(job_finish, &job_config, &mut job_states, &mut dispatcher, config.get_state_path());
+                    next_run.invalidate(id);
+                    next_run.update_and_get_passive(id, definition.get_frequency(), passive, timestamp_now, &run_log);
+                }
+                continue;
+            }
             let next = next_run.update_and_get(id, definition.get_frequency(), now, &run_log, &running);
             if timestamp_now >= next {
+                if job_states.is_paused(id) {
+                    debug!("Job {} is due to run, but is paused, so it will not be run.", id);
+                    next_run.skip(id, definition.get_frequency(), timestamp_now);
+                    continue;
+                }
                 if !definition.allow_parallel() && running.any_running(&id) {
                     debug!("Job {} is due to run, but is already running, so it will not be run yet.", id);
+                    if let Some(skipped) = next_run.skip(id, definition.get_frequency(), timestamp_now) {
+                        job_states.record_skipped(id.clone(), skipped);
+                        spawn_file_write("job state", job_states.write_to_string(), config.get_state_path().clone());
+
+                        let running_since = running.get_earliest(id).unwrap_or(skipped);
+                        let skipped_count = job_states.get_or_default(id).count_skipped_since(running_since);
+                        warn!("[{id}] Still running after {}, skipped {skipped_count} scheduled runs.",
+                              format_duration(timestamp_now.saturating_sub(running_since)));
+                        // Only notify about the first skipped run, rather than every one while the run continues.
+                        if running.mark_overrun_notified(id, running_since) {
+                            let notification = definition.get_notify_definition()
+                                .create_overrun_message(id, running_since, skipped_count, &job_config.get_author(definition), timestamp_now);
+                            if let Some(notification) = notification {
+                                dispatcher.dispatch(notification, &job_config);
+                            }
+                        }
+                    }
                     continue;
                 }
                 debug!("Job {} is due to run.", id);
 
                 running.add(id.clone(), timestamp_now);
                 next_run.invalidate(id);
-                next_run.update_and_get(id, definition.get_frequency(), now + chrono::Duration::seconds(1), &run_log, &running);
+                let next = next_run.update_and_get(id, definition.get_frequency(), now + chrono::Duration::seconds(1), &run_log, &running);
 
                 // Run task.
-                spawn_job(id.clone(), definition.get_cmd().clone(), definition.get_notify_definition().clone(),
-                          config.get_rnotify_config().clone(), timestamp_now, send.clone());
+                let job_run = JobRun::new(id, definition, &job_config, config.get_redactor(id), job_states.get_or_default(id), timestamp_now, next);
+                spawn_job(job_run, send.clone());
             }
         }
 
+        let warn_wait = warn_running_long(&job_config, &mut running, &mut dispatcher, timestamp_now);
+        let deadline_wait = check_deadlines(&job_config, &mut job_states, &running, &mut dispatcher,
+                                            config.get_state_path(), timestamp_now);
+
         let short_wait = next_run.get_wait(timestamp_now);
         let sleep = min(short_wait, CHECK_INTERVAL);
+        let sleep = dispatch_wait.map_or(sleep, |wait| min(sleep, wait));
+        let sleep = warn_wait.map_or(sleep, |wait| min(sleep, wait));
+        let sleep = deadline_wait.map_or(sleep, |wait| min(sleep, wait));
+        let sleep = job_states.get_next_pause_end()
+            .map_or(sleep, |until| min(sleep, Duration::from_secs(until.saturating_sub(timestamp_now))));
+        let sleep = waiting.get_next_timeout()
+            .map_or(sleep, |until| min(sleep, Duration::from_secs(until.saturating_sub(timestamp_now))));
 
         tokio::select!(
             _ = tokio::time::sleep(sleep) => {
@@ -111,8 +278,146 @@ async fn main_loop(config: AllConfig, mut run_log: RunLog, rt: &Runtime) {
                 if !s.is_empty() {
                     warn!("Some jobs are still running: {}", s);
                 }
+                #[cfg(unix)]
+                control_server::remove_socket(config.get_socket_path());
                 return;
             }
+            http_request = http_recv.recv() => {
+                let request = match http_request {
+                    Some(request) => request,
+                    None => continue,
+                };
+                match request.get_route().clone() {
+                    Route::RunJob { job: id, wait } => match job_config.entries().get(&id).filter(|definition| !definition.is_passive()) {
+                        None => request.respond(Response::not_found(&format!("No job {id}"))),
+                        Some(_) => match triggered.queue(id.clone(), Local::now().timestamp() as u64) {
+                            Ok(run_id) => {
+                                info!("[{id}] Run {run_id} triggered over HTTP.");
+                                if wait {
+                                    waiting.add(run_id, request, Local::now().timestamp() as u64);
+                                } else {
+                                    request.respond(Response::json(202, &triggered.get(run_id)));
+                                }
+                            }
+                            Err(err) => {
+                                warn!("[{id}] Not triggering a run over HTTP: {err}");
+                                request.respond(Response::too_many_requests(&err));
+                            }
+                        },
+                    },
+                    Route::GetRun { id } => match triggered.get(id) {
+                        Some(run) => request.respond(Response::json(200, run)),
+                        None => request.respond(Response::not_found(&format!("No run {id}"))),
+                    },
+                    Route::Ping { job: id, kind } => match job_config.entries().get(&id).filter(|definition| definition.is_passive()) {
+                        None => request.respond(Response::not_found(&format!("No passive job {id}"))),
+                        Some(definition) => {
+                            let timestamp_now = Local::now().timestamp() as u64;
+                            info!("[{id}] Pinged {:?}", kind);
+                            if kind == PingKind::Start {
+                                running.add(id.clone(), timestamp_now);
+                            } else {
+                                let started = running.get_earliest(&id);
+                                if let Some(started) = started {
+                                    running.mark_completed(&id, started);
+                                }
+                                let started = started.unwrap_or(timestamp_now);
+                                let result = if kind == PingKind::Success { ResultKind::Ok } else { ResultKind::Failed };
+                                let notify_definition = definition.get_notify_definition();
+                                let previous = job_states.get_or_default(&id);
+                                let outcome = passive::create_ping_outcome(&id, notify_definition, &config.get_redactor(&id), &previous,
+                                                                           result, request.get_body(), timestamp_now.saturating_sub(started));
+                                let job_finish = finish_run(id.clone(), notify_definition, &job_config.get_author(definition),
+                                                            &previous, started, outcome);
+                                // Passive jobs are next expected relative to when they finished.
+                                run_log.record(id.clone(), timestamp_now);
+                                spawn_file_write("run log", run_log.write_to_string(), config.get_run_log_path().clone());
+                                record_finish(job_finish, &job_config, &mut job_states, &mut dispatcher, config.get_state_path());
+                            }
+                            next_run.invalidate(&id);
+                            request.respond(Response::ok("OK"));
+                        }
+                    },
+                }
+            }
+            command = control_recv.recv() => {
+                let command: ControlCommand = match command {
+                    Some(command) => command,
+                    None => continue,
+                };
+                let timestamp_now = Local::now().timestamp() as u64;
+                let response = match command.get_request().clone() {
+                    ControlRequest::Status => ControlResponse::ok(describe::describe_status(&job_config, &job_states, &running, &triggered,
+                                                                                            &dispatcher, started, timestamp_now)),
+                    ControlRequest::List => ControlResponse::ok(describe::describe_jobs(&job_config, &job_states, &running, timestamp_now)),
+                    ControlRequest::Next => ControlResponse::ok(describe::describe_next_runs(&job_config, &job_states, &next_run)),
+                    ControlRequest::Run { job: id } => match job_config.entries().get(&id) {
+                        None => ControlResponse::error(format!("No job {id}")),
+                        Some(definition) if definition.is_passive() => ControlResponse::error(format!("{id} is passive, so can't be run")),
+                        Some(_) => match triggered.queue(id.clone(), timestamp_now) {
+                            Ok(run_id) => {
+                                info!("[{id}] Run {run_id} triggered through the control socket.");
+                                ControlResponse::ok(format!("Queued run {run_id} of {id}"))
+                            }
+                            Err(err) => ControlResponse::error(err),
+                        }
+                    },
+                    ControlRequest::Silence { job, tag, duration, reason } => {
+                        if job.as_ref().is_some_and(|id| !job_config.entries().contains_key(id)) {
+                            ControlResponse::error(format!("No job {}", job.unwrap()))
+                        } else {
+                            let target = SilenceTarget::new(job, tag);
+                            let description = target.describe();
+                            let until = Local::now() + chrono::Duration::seconds(duration as i64);
+                            dispatcher.add_silence(Silence::new(target, until, reason));
+                            ControlResponse::ok(format!("Silenced {description} until {}", until.format("%Y-%m-%d %H:%M")))
+                        }
+                    }
+                    ControlRequest::Pause { job: id, duration } => match job_config.entries().get(&id) {
+                        None => ControlResponse::error(format!("No job {id}")),
+                        Some(_) => {
+                            let until = duration.map(|duration| timestamp_now + duration);
+                            job_states.set_pause(id.clone(), Some(JobPause::new(timestamp_now, until)));
+                            spawn_file_write("job state", job_states.write_to_string(), config.get_state_path().clone());
+                            let output = match until {
+                                Some(until) => format!("Paused {id} for {}", format_duration(until - timestamp_now)),
+                                None => format!("Paused {id} until it is resumed"),
+                            };
+                            info!("[{id}] {output}");
+                            ControlResponse::ok(output)
+                        }
+                    },
+                    ControlRequest::Resume { job: id } => {
+                        if job_states.is_paused(&id) {
+                            job_states.set_pause(id.clone(), None);
+                            spawn_file_write("job state", job_states.write_to_string(), config.get_state_path().clone());
+                            info!("[{id}] Resumed.");
+                            ControlResponse::ok(format!("Resumed {id}"))
+                        } else {
+                            ControlResponse::error(format!("{id} isn't paused"))
+                        }
+                    }
+                    ControlRequest::Reload => {
+                        match reload_config(&mut config, &mut job_config, &mut job_states, &mut next_run, &mut triggered,
+                                            &mut waiting, &mut dispatcher) {
+                            Ok(output) => {
+                                info!("{output}");
+                                ControlResponse::ok(output)
+                            }
+                            Err(err) => {
+                                error!("Failed to reload config, continuing with the current config: {err}");
+                                ControlResponse::error(err)
+                            }
+                        }
+                    }
+                };
+                command.respond(response);
+            }
+            delivery = delivery_recv.recv() => {
+                if let Some(delivery) = delivery {
+                    dispatcher.delivered(delivery, &job_config);
+                }
+            }
             job_finish = recv.recv() => {
                 if job_finish.is_none() {
                     error!("Job finish receiver had the other end dropped");
@@ -122,71 +427,289 @@ async fn main_loop(config: AllConfig, mut run_log: RunLog, rt: &Runtime) {
                 debug!("Job finished: {:?}", job_finish);
                 running.mark_completed(&job_finish.id, job_finish.started);
 
-                run_log.record(job_finish.id, job_finish.started);
-                spawn_runlog_write(run_log.write_to_string(), config.get_run_log_path().clone());
+                run_log.record(job_finish.id.clone(), job_finish.started);
+                spawn_file_write("run log", run_log.write_to_string(), config.get_run_log_path().clone());
+                if let Some(run_id) = job_finish.trigger {
+                    if let Some(run) = triggered.finish(run_id, job_finish.record.get_result(), job_finish.record.get_duration()) {
+                        for request in waiting.take(run_id) {
+                            request.respond(Response::json(200, run));
+                        }
+                    }
+                }
+                record_finish(job_finish, &job_config, &mut job_states, &mut dispatcher, config.get_state_path());
             }
         );
     }
 }
 
+/// Re-reads the configs, keeping the state of the jobs, and leaving running jobs to finish.
+/// Queued triggered runs of jobs that were removed are cancelled.
+/// Gives a description of the jobs that changed, or an error if the new config is invalid or changes the http listener.
+fn reload_config(config: &mut AllConfig, job_config: &mut Config, job_states: &mut JobStates, next_run: &mut NextRun,
+                 triggered: &mut TriggeredRuns, waiting: &mut WaitingRequests, dispatcher: &mut Dispatcher) -> Result<String, String> {
+    config.reload()?;
+    let new_config = config.get_job_config().clone();
+    let changes = job_config.diff_jobs(&new_config);
+
+    for id in changes.get_removed().iter().chain(changes.get_changed()) {
+        next_run.invalidate(id);
+    }
+    for id in changes.get_changed() {
+        let old = job_config.entries()[id].get_must_succeed_by();
+        if new_config.entries()[id].get_must_succeed_by() != old {
+            job_states.clear_deadline(id);
+        }
+    }
+    for (run_id, id) in triggered.get_queued() {
+        let runnable = new_config.entries().get(&id).is_some_and(|definition| !definition.is_passive());
+        if !runnable {
+            if let Some(run) = triggered.cancel(run_id) {
+                warn!("[{id}] Cancelled triggered run {run_id}, as the job was removed.");
+                for request in waiting.take(run_id) {
+                    request.respond(Response::json(200, run));
+                }
+            }
+        }
+    }
+    dispatcher.set_routes(config.get_routes().clone());
+    *job_config = new_config;
+
+    if changes.is_empty() {
+        return Ok(String::from("Reloaded config, no jobs changed."));
+    }
+    let describe = |name: &str, ids: &Vec<JobDefinitionId>| {
+        Some(ids).filter(|ids| !ids.is_empty())
+            .map(|ids| format!("{name}: {}", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")))
+    };
+    let described: Vec<String> = [
+        describe("added", changes.get_added()),
+        describe("removed", changes.get_removed()),
+        describe("changed", changes.get_changed()),
+    ].into_iter().flatten().collect();
+    Ok(format!("Reloaded config, {}.", described.join("; ")))
+}
+
+/// Records the state of the job after a run, and sends the notifications about it.
+fn record_finish(job_finish: JobFinish, job_config: &Config, job_states: &mut JobStates, dispatcher: &mut Dispatcher, state_path: &Path) {
+    if let Some(stdout) = job_finish.stdout {
+        job_states.set_last_stdout(job_finish.id.clone(), stdout);
+    }
+    job_states.set_alert(job_finish.id.clone(), job_finish.alert.get_alert().cloned(), job_finish.alert.is_flapping());
+    job_states.record(job_finish.id.clone(), job_finish.record);
+    spawn_file_write("job state", job_states.write_to_string(), state_path.to_path_buf());
+
+    if let Some(notification) = job_finish.notification {
+        dispatcher.dispatch(notification, job_config);
+    }
+    if let Some(notification) = job_finish.duration_notification {
+        dispatcher.dispatch(notification, job_config);
+    }
+}
+
+/// Alerts about jobs which haven't succeeded by their deadline, and schedules their next deadline.
+/// Gives how long until the next deadline, if any.
+fn check_deadlines(job_config: &Config, job_states: &mut JobStates, running: &RunningJobs, dispatcher: &mut Dispatcher,
+                   state_path: &Path, now: u64) -> Option<Duration> {
+    let mut changed = false;
+    let mut next_due: Option<u64> = None;
+    for (id, definition) in job_config.entries() {
+        let must_succeed_by = match definition.get_must_succeed_by() {
+            Some(must_succeed_by) => must_succeed_by,
+            None => continue,
+        };
+        let state = job_states.get_or_default(id);
+        let check = deadline::check(must_succeed_by, state.get_deadline(), &state, now);
+        if let Some(missed) = check.get_missed().filter(|_| !state.is_paused()) {
+            warn!("[{id}] Has not succeeded by its deadline.");
+            let notification = definition.get_notify_definition()
+                .create_deadline_message(id, missed, &state, running.get_earliest(id), &job_config.get_author(definition), now);
+            dispatcher.dispatch(notification, job_config);
+        }
+        let next = *check.get_next();
+        if state.get_deadline() != Some(next) {
+            debug!("[{id}] Must next succeed by {}", next.get_due());
+            job_states.set_deadline(id.clone(), next);
+            changed = true;
+        }
+        next_due = Some(next_due.map_or(next.get_due(), |due| min(due, next.get_due())));
+    }
+    if changed {
+        spawn_file_write("job state", job_states.write_to_string(), state_path.to_path_buf());
+    }
+    next_due.map(|due| Duration::from_secs(due.saturating_sub(now)))
+}
+
+/// Warns about runs which have been running for longer than their job's warn_after, once per run.
+/// Gives how long until the next run will need to be warned about, if any.
+fn warn_running_long(job_config: &Config, running: &mut RunningJobs, dispatcher: &mut Dispatcher, now: u64) -> Option<Duration> {
+    let runs: Vec<(JobDefinitionId, u64)> = running.get_running().iter()
+        .flat_map(|(id, starts)| starts.iter().map(move |started| (id.clone(), *started)))
+        .collect();
+    let mut next_warn: Option<u64> = None;
+    for (id, started) in runs {
+        let definition = match job_config.entries().get(&id) {
+            Some(definition) => definition,
+            None => continue,
+        };
+        let notify_definition = definition.get_notify_definition();
+        let warn_time = match notify_definition.get_warn_time(started) {
+            Some(warn_time) => warn_time,
+            None => continue,
+        };
+        if now < warn_time {
+            next_warn = Some(next_warn.map_or(warn_time, |next| min(next, warn_time)));
+            continue;
+        }
+        if !running.mark_warned(&id, started) {
+            continue;
+        }
+        warn!("[{id}] Still running after {}.", format_duration(now.saturating_sub(started)));
+        let notification = notify_definition.create_running_long_message(&id, started, &job_config.get_author(definition), now);
+        if let Some(notification) = notification {
+            dispatcher.dispatch(notification, job_config);
+        }
+    }
+    next_warn.map(|next| Duration::from_secs(next.saturating_sub(now)))
+}
+
 #[derive(Debug)]
 pub struct JobFinish {
     id: JobDefinitionId,
     started: u64,
-    success: bool,
+    record: RunRecord,
+    stdout: Option<String>,
+    alert: AlertUpdate,
+    notification: Option<Notification>,
+    /// The notification about the run taking an unusual amount of time, if it did.
+    duration_notification: Option<Notification>,
+    /// The id of the triggered run, if it was triggered rather than scheduled.
+    trigger: Option<u64>,
 }
 
 impl JobFinish {
-    fn new(id: JobDefinitionId, started: u64, success: bool) -> Self {
+    fn new(id: JobDefinitionId, started: u64, record: RunRecord, stdout: Option<String>,
+           alert: AlertUpdate, notification: Option<Notification>) -> Self {
         Self {
             id,
             started,
-            success,
+            record,
+            stdout,
+            alert,
+            notification,
+            duration_notification: None,
+            trigger: None,
         }
     }
+
+    fn with_trigger(mut self, trigger: Option<u64>) -> Self {
+        self.trigger = trigger;
+        self
+    }
+
+    fn with_duration_notification(mut self, duration_notification: Option<Notification>) -> Self {
+        self.duration_notification = duration_notification;
+        self
+    }
 }
 
-fn spawn_runlog_write(s: String, loc: PathBuf) {
+fn spawn_file_write(name: &'static str, s: String, loc: PathBuf) {
     tokio::spawn(async move {
         match std::fs::write(loc, s) {
             Ok(_) => {
-                debug!("Wrote run log.");
+                debug!("Wrote {name}.");
             }
             Err(err) => {
-                error!("Error writing run log: {err}");
+                error!("Error writing {name}: {err}");
             }
         }
     });
 }
 
-fn spawn_job(id: JobDefinitionId, cmd: String, notify_definition: NotifyDefinition,
-             rnotify_config: rnotifylib::config::Config, start_timestamp: u64, job_finish_sender: Sender<JobFinish>) {
-    tokio::task::spawn(run_job(id, cmd, notify_definition, rnotify_config, start_timestamp, job_finish_sender));
+/// Everything needed to run a job once.
+struct JobRun {
+    id: JobDefinitionId,
+    definition: JobDefinition,
+    redactor: Arc<Redactor>,
+    author: Template,
+    /// The state of the job before this run.
+    previous: JobState,
+    started: u64,
+    next_run: Option<u64>,
+    /// The id of the triggered run, if it was triggered rather than scheduled.
+    trigger: Option<u64>,
 }
 
-async fn run_job(id: JobDefinitionId, cmd: String, notify_definition: NotifyDefinition,
-                 rnotify_config: rnotifylib::config::Config, start_timestamp: u64, job_finish_sender: Sender<JobFinish>) {
-    info!("[{id}] Running at {}", Local::now().to_rfc3339_opts(SecondsFormat::Millis, true));
-    let output = action::execute(&cmd, notify_definition.get_output_format()).await;
-    if let JobResult::Invalid(err) = &output {
-        error!("[{id}] Failed to run job: {:?}", err);
+impl JobRun {
+    fn new(id: &JobDefinitionId, definition: &JobDefinition, job_config: &Config, redactor: Arc<Redactor>,
+           previous: JobState, started: u64, next_run: u64) -> Self {
+        Self {
+            id: id.clone(),
+            definition: definition.clone(),
+            redactor,
+            author: job_config.get_author(definition),
+            previous,
+            started,
+            next_run: Some(next_run).filter(|next| *next != u64::MAX),
+            trigger: None,
+        }
     }
-    let succ = matches!(&output, JobResult::Ok(_));
-    let job_finish = JobFinish::new(id.clone(), start_timestamp, succ);
 
-    info!("[{id}] Job had outcome {}", output.type_str());
-    match notify_definition.create_message(&id, output) {
-        None => info!("[{id}] Didn't need a rnotify message to be sent"),
-        Some(message) => {
-            match rnotifylib::send_message(message, &rnotify_config) {
-                Ok(()) => info!("[{id}] Sent a message to rnotify."),
-                Err(errs) => error!("[{id}] Failed to send a message to rnotify {}", errs),
-            }
-        }
+    fn with_trigger(mut self, trigger: u64) -> Self {
+        self.trigger = Some(trigger);
+        self
     }
+}
+
+fn spawn_job(job_run: JobRun, job_finish_sender: Sender<JobFinish>) {
+    tokio::task::spawn(run_job(job_run, job_finish_sender));
+}
+
+async fn run_job(job_run: JobRun, job_finish_sender: Sender<JobFinish>) {
+    let JobRun { id, definition, redactor, author, previous, started: start_timestamp, next_run, trigger } = job_run;
+    info!("[{id}] Running at {}", Local::now().to_rfc3339_opts(SecondsFormat::Millis, true));
+    let notify_definition = definition.get_notify_definition();
+    let outcome = action::execute(&id, definition.get_cmd(), notify_definition, &redactor, &previous,
+                                    start_timestamp, next_run).await;
+    if let JobResult::Invalid(err) = outcome.get_result() {
+        error!("[{id}] Failed to run job: {:?}", err);
+    }
+    let job_finish = finish_run(id, notify_definition, &author, &previous, start_timestamp, outcome)
+        .with_trigger(trigger);
 
     if let Err(e) = job_finish_sender.send(job_finish).await {
         error!("Failed to record job finishing: {}", e);
     }
 
 }
+
+/// Evaluates the outcome of a run against the job's previous state, creating what to record about it,
+/// and the notifications to send.
+fn finish_run(id: JobDefinitionId, notify_definition: &NotifyDefinition, author: &Template, previous: &JobState,
+              start_timestamp: u64, mut outcome: JobOutcome) -> JobFinish {
+    let kind = outcome.get_result().kind();
+    let alert_update = alert::evaluate(previous, kind, start_timestamp, &notify_definition.get_alert_policy());
+    let record = RunRecord::new(start_timestamp, kind, outcome.get_metrics().clone())
+        .with_duration(outcome.get_duration());
+    let stdout = outcome.take_stdout();
+
+    info!("[{id}] Job had outcome {}", outcome.get_result().type_str());
+    // Unsuccessful runs often stop early, or hang, so only successful runs are compared to the usual duration.
+    let duration_notification = match kind.is_ok() {
+        true => notify_definition.create_duration_message(&id, outcome.get_duration(), previous,
+                                                          author, Local::now().timestamp() as u64),
+        false => None,
+    };
+    if duration_notification.is_some() {
+        warn!("[{id}] Took an unusual amount of time, {}", format_duration(outcome.get_duration()));
+    }
+    if alert_update.get_event() == &AlertEvent::Suppressed {
+        info!("[{id}] Not alerting yet, {} consecutive unsuccessful runs", previous.get_consecutive_unsuccessful_runs() + 1);
+    }
+    let notification = notify_definition.create_message(&id, outcome, alert_update.get_alert(),
+                                                          alert_update.get_event(), author);
+    if notification.is_none() {
+        info!("[{id}] Didn't need a rnotify message to be sent");
+    }
+    JobFinish::new(id, start_timestamp, record, stdout, alert_update, notification)
+        .with_duration_notification(duration_notification)
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+/// Numeric values reported by a job, keyed by metric name.
+pub type Metrics = BTreeMap<String, f64>;
+
+/// Extracts metrics from a program's output.
+/// Each metric must be on its own line, in the format:
+///     disk_used_pct=93
+/// Lines which are not in that format are ignored.
+pub fn extract_metrics(s: &str) -> Metrics {
+    let mut metrics = Metrics::new();
+    for line in s.lines() {
+        let (name, value) = match line.trim().split_once('=') {
+            Some(x) => x,
+            None => continue,
+        };
+        let name = name.trim();
+        if !is_valid_name(name) {
+            continue;
+        }
+        if let Ok(value) = value.trim().parse::<f64>() {
+            if value.is_finite() {
+                metrics.insert(name.to_owned(), value);
+            }
+        }
+    }
+    metrics
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The levels at which a metric becomes a warning or critical.
+/// A metric reaches a level when it is greater than or equal to it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Threshold {
+    warn: Option<f64>,
+    crit: Option<f64>,
+}
+
+impl Threshold {
+    pub fn new(warn: Option<f64>, crit: Option<f64>) -> Self {
+        Self {
+            warn,
+            crit,
+        }
+    }
+
+    pub fn evaluate(&self, value: f64) -> ThresholdLevel {
+        if self.crit.filter(|crit| value >= *crit).is_some() {
+            return ThresholdLevel::Critical;
+        }
+        if self.warn.filter(|warn| value >= *warn).is_some() {
+            return ThresholdLevel::Warning;
+        }
+        ThresholdLevel::Normal
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThresholdLevel {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl ThresholdLevel {
+    pub fn type_str(&self) -> &str {
+        match self {
+            ThresholdLevel::Normal => "normal",
+            ThresholdLevel::Warning => "warning",
+            ThresholdLevel::Critical => "critical",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let s = "Checking disks\ndisk_used_pct=93\ninodes_used_pct = 12.5\nnot a metric=5\nbad_value=abc";
+        let mut expected = Metrics::new();
+        expected.insert("disk_used_pct".to_owned(), 93.0);
+        expected.insert("inodes_used_pct".to_owned(), 12.5);
+
+        assert_eq!(expected, extract_metrics(s));
+    }
+
+    #[test]
+    fn test_threshold() {
+        let threshold = Threshold::new(Some(80.0), Some(90.0));
+        assert_eq!(ThresholdLevel::Normal, threshold.evaluate(71.0));
+        assert_eq!(ThresholdLevel::Warning, threshold.evaluate(80.0));
+        assert_eq!(ThresholdLevel::Critical, threshold.evaluate(93.0));
+
+        let warn_only = Threshold::new(Some(10.0), None);
+        assert_eq!(ThresholdLevel::Warning, warn_only.evaluate(100.0));
+    }
+}
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeZone};
 use rnotifydlib::config::JobDefinitionId;
 use rnotifydlib::frequency::Frequency;
+use rnotifydlib::passive::PassiveDefinition;
 use std::time::Duration;
 use crate::RunLog;
 
@@ -30,6 +31,42 @@ impl NextRun {
         })
     }
 
+    /// Calculates, caches, and gets the timestamp by which the passive job with the given id must next be pinged.
+    /// run_log: the last time the job pinged that it finished.
+    pub fn update_and_get_passive(&mut self, id: &JobDefinitionId, frequency: &Frequency, passive: &PassiveDefinition,
+                                  now: u64, run_log: &RunLog) -> u64 {
+        *self.map.entry(id.clone()).or_insert_with(|| {
+            passive.get_deadline(frequency, run_log.get_last_successful_run_time(id), now)
+        })
+    }
+
+    /// Skips the scheduled runs of the job up to and including now, as it can't be run yet.
+    /// Gives the scheduled time that was skipped, or None if the job isn't due.
+    pub fn skip(&mut self, id: &JobDefinitionId, frequency: &Frequency, now: u64) -> Option<u64> {
+        let skipped = *self.map.get(id)?;
+        if skipped > now {
+            return None;
+        }
+        let next = match frequency {
+            // Keep to the same period, rather than restarting the period from now.
+            Frequency::FixedPeriod(period) => {
+                let period = period.as_seconds().max(1);
+                skipped + ((now - skipped) / period + 1) * period
+            }
+            _ => {
+                let after_now = Local.timestamp_opt(now as i64 + 1, 0).single().unwrap_or_else(Local::now);
+                frequency.next(&after_now, None)
+            }
+        };
+        self.map.insert(id.clone(), next);
+        Some(skipped)
+    }
+
+    /// Expects the passive job to next be pinged as if it was pinged now, as it isn't alerted about while paused.
+    pub fn skip_passive(&mut self, id: &JobDefinitionId, frequency: &Frequency, passive: &PassiveDefinition, now: u64) {
+        self.map.insert(id.clone(), passive.get_deadline(frequency, Some(now), now));
+    }
+
     /// Invalidate the cached timestamp for a particular job id
     /// Should be used if the data it was calculated on is now incorrect:
     /// - The job has since run
@@ -38,6 +75,11 @@ impl NextRun {
         self.map.remove(id);
     }
 
+    /// Gets the cached time the job is next due, see [NextRun::update_and_get]
+    pub fn get(&self, id: &JobDefinitionId) -> Option<u64> {
+        self.map.get(id).copied()
+    }
+
     /// Gets the duration to wait until the next job is due to be run.
     pub fn get_wait(&self, now: u64) -> Duration {
         Duration::from_secs(self.map.values()
@@ -49,3 +91,26 @@ impl NextRun {
 pub trait ProvisionalJobRuns {
     fn get_latest(&self, id: &JobDefinitionId) -> Option<u64>;
 }
+
+#[cfg(test)]
+mod test {
+    use rnotifydlib::frequency::FixedPeriodInner;
+    use super::*;
+
+    #[test]
+    fn test_skip() {
+        let id = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        let frequency = Frequency::FixedPeriod(FixedPeriodInner::new(0, 30, 0));
+        let mut next_run = NextRun::new();
+        assert_eq!(None, next_run.skip(&id, &frequency, 1000));
+
+        next_run.map.insert(id.clone(), 1000);
+        assert_eq!(None, next_run.skip(&id, &frequency, 999));
+        assert_eq!(Some(1000), next_run.skip(&id, &frequency, 1000));
+        assert_eq!(Some(2800), next_run.get(&id));
+
+        // Keeps to the same period when several runs were missed.
+        assert_eq!(Some(2800), next_run.skip(&id, &frequency, 2800 + 1800 * 2 + 5));
+        assert_eq!(Some(2800 + 1800 * 3), next_run.get(&id));
+    }
+}
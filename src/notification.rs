@@ -0,0 +1,65 @@
+use rnotifylib::message::author::Author;
+use rnotifylib::message::{Level, Message};
+use crate::config::JobDefinitionId;
+use crate::host;
+use crate::job_state::ResultKind;
+
+/// The author of messages from rnotifyd itself, rather than about a particular job.
+pub fn daemon_author() -> Author {
+    Author::parse(format!("rnotifyd@{}", host::get_hostname()))
+}
+
+/// Orders levels by how urgent they are.
+pub fn severity(level: &Level) -> u8 {
+    match level {
+        Level::Info | Level::SelfInfo => 0,
+        Level::Warn => 1,
+        Level::Error | Level::SelfError => 2,
+    }
+}
+
+/// A message about a job, which is ready to be sent to rnotify.
+#[derive(Debug)]
+pub struct Notification {
+    job_id: JobDefinitionId,
+    result: ResultKind,
+    level: Level,
+    title: String,
+    message: Message,
+}
+
+impl Notification {
+    pub fn new(job_id: JobDefinitionId, result: ResultKind, level: Level, title: String, message: Message) -> Self {
+        Self {
+            job_id,
+            result,
+            level,
+            title,
+            message,
+        }
+    }
+
+    pub fn get_job_id(&self) -> &JobDefinitionId {
+        &self.job_id
+    }
+
+    pub fn get_result(&self) -> ResultKind {
+        self.result
+    }
+
+    pub fn get_level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn into_message(self) -> Message {
+        self.message
+    }
+}
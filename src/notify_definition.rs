@@ -1,54 +1,611 @@
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{Local, TimeZone};
 use rnotifylib::message::author::Author;
 use rnotifylib::message::component::Component;
-use rnotifylib::message::{Level, Message};
+use rnotifylib::message::{Level, Message, MessageDetail};
+use crate::frequency::FixedPeriodInner;
 use crate::config::JobDefinitionId;
 use serde::{Serialize, Deserialize};
 use crate::action::ProgramOutputFormat;
-use crate::job_result::JobResult;
+use crate::deadline::PendingDeadline;
+use crate::duration_format::format_duration;
+use crate::host;
+use crate::job_result::{JobOutcome, JobResult};
+use crate::job_state::{JobState, ResultKind};
+use crate::alert::{Alert, AlertEvent, AlertPolicy, FlapDetection};
+use crate::metrics::{Metrics, Threshold, ThresholdLevel};
+use crate::notification::{severity, Notification};
+use crate::quiet_hours::QuietHours;
+use crate::template::{Template, TemplateContext, TemplateVariable};
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct NotifyDefinition {
-    title: String,
+    title: Template,
+    /// Text shown at the start of the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<Template>,
     component: Component,
     output_format: ProgramOutputFormat,
     report_if_success: bool,
+    #[serde(default)]
+    notify_on: NotifyOn,
+    /// The number of consecutive unsuccessful runs needed before an alert is raised.
+    #[serde(default = "default_alert_threshold")]
+    failure_threshold: u32,
+    /// The number of consecutive successful runs needed before an alert is resolved.
+    #[serde(default = "default_alert_threshold")]
+    recovery_threshold: u32,
+    /// How often to remind about an ongoing alert.
+    /// If not set, ongoing alerts are notified every run, or never if notifying on state changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    renotify_interval: Option<FixedPeriodInner>,
+    /// Raises the level of messages about an alert once it has been ongoing for a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escalation: Option<Escalation>,
+    /// Sends a single message when the job alternates between success and failure too often,
+    /// rather than one for each change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flapping: Option<FlapDetection>,
+    /// Whether Info and Warn notifications are held back for the digest.
+    /// If not set, the digest's all_jobs setting is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<bool>,
+    /// Quiet hours for this job, instead of the global quiet hours.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quiet_hours: Option<QuietHours>,
+    /// Overrides the level messages are sent at for each result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ok_level: Option<Level>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    warning_level: Option<Level>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failed_level: Option<Level>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    invalid_level: Option<Level>,
+    /// The names of the rnotify configs to send messages through, see [Config::get_routes](crate::config::Config::get_routes).
+    /// "default" is the main rnotify config, which is used if this is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    routes: Option<Vec<String>>,
+    /// Report successful runs whose stdout differs from the previous successful run.
+    #[serde(default)]
+    report_if_changed: bool,
+    /// The author of messages about this job, instead of the global author.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<Template>,
+    /// The level to notify at when the job is still running at its next scheduled time,
+    /// so that run is skipped. If not set, overruns are only logged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    overrun_level: Option<Level>,
+    /// Warns while the job is still running, once it has been running for this long.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    warn_after: Option<FixedPeriodInner>,
+    /// Flags completed runs which took much more or less time than usual.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expected_duration: Option<ExpectedDuration>,
+    /// Thresholds for metrics reported by the job, keyed by metric name.
+    /// Metrics are only read from the output of jobs that have thresholds.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    thresholds: BTreeMap<String, Threshold>,
 }
 
 impl NotifyDefinition {
-    pub fn new(title: String, component: Component, report_if_success: bool, output_format: ProgramOutputFormat) -> Self {
+    pub fn new(title: Template, component: Component, report_if_success: bool, output_format: ProgramOutputFormat) -> Self {
         Self {
             title,
+            summary: None,
             component,
             report_if_success,
             output_format,
+            notify_on: NotifyOn::default(),
+            failure_threshold: default_alert_threshold(),
+            recovery_threshold: default_alert_threshold(),
+            renotify_interval: None,
+            escalation: None,
+            flapping: None,
+            digest: None,
+            quiet_hours: None,
+            ok_level: None,
+            warning_level: None,
+            failed_level: None,
+            invalid_level: None,
+            routes: None,
+            author: None,
+            overrun_level: None,
+            warn_after: None,
+            expected_duration: None,
+            report_if_changed: false,
+            thresholds: BTreeMap::new(),
         }
     }
 
+    pub fn with_thresholds(mut self, thresholds: BTreeMap<String, Threshold>) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
     pub fn get_output_format(&self) -> &ProgramOutputFormat {
         &self.output_format
     }
 
-    pub fn create_message(&self, job_id: &JobDefinitionId, job_result: JobResult) -> Option<Message> {
-        let author = Author::parse(format!("rnotifyd/{}", job_id));
+    pub fn with_notify_on(mut self, notify_on: NotifyOn) -> Self {
+        self.notify_on = notify_on;
+        self
+    }
+
+    pub fn with_alert_thresholds(mut self, failure_threshold: u32, recovery_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold;
+        self.recovery_threshold = recovery_threshold;
+        self
+    }
+
+    pub fn with_renotify_interval(mut self, renotify_interval: FixedPeriodInner) -> Self {
+        self.renotify_interval = Some(renotify_interval);
+        self
+    }
+
+    pub fn get_renotify_interval(&self) -> Option<&FixedPeriodInner> {
+        self.renotify_interval.as_ref()
+    }
+
+    pub fn with_escalation(mut self, escalation: Escalation) -> Self {
+        self.escalation = Some(escalation);
+        self
+    }
+
+    pub fn with_flap_detection(mut self, flap_detection: FlapDetection) -> Self {
+        self.flapping = Some(flap_detection);
+        self
+    }
+
+    pub fn with_digest(mut self, digest: bool) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    pub fn get_digest(&self) -> Option<bool> {
+        self.digest
+    }
+
+    pub fn with_quiet_hours(mut self, quiet_hours: QuietHours) -> Self {
+        self.quiet_hours = Some(quiet_hours);
+        self
+    }
+
+    pub fn get_quiet_hours(&self) -> Option<&QuietHours> {
+        self.quiet_hours.as_ref()
+    }
+
+    /// Overrides the level that messages about the given result are sent at.
+    pub fn with_level(mut self, kind: ResultKind, level: Level) -> Self {
+        match kind {
+            ResultKind::Ok => self.ok_level = Some(level),
+            ResultKind::Warning => self.warning_level = Some(level),
+            ResultKind::Failed => self.failed_level = Some(level),
+            ResultKind::Invalid => self.invalid_level = Some(level),
+        }
+        self
+    }
+
+    /// Gets the level that messages about the given result are sent at.
+    pub fn get_level(&self, kind: ResultKind) -> Level {
+        let (level, default) = match kind {
+            ResultKind::Ok => (&self.ok_level, Level::Info),
+            ResultKind::Warning => (&self.warning_level, Level::Warn),
+            ResultKind::Failed => (&self.failed_level, Level::Error),
+            ResultKind::Invalid => (&self.invalid_level, Level::SelfError),
+        };
+        level.clone().unwrap_or(default)
+    }
+
+    pub fn with_routes(mut self, routes: Vec<String>) -> Self {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// Gets the names of the rnotify configs to send messages through.
+    pub fn get_routes(&self) -> Vec<String> {
+        self.routes.clone().unwrap_or_else(|| vec![String::from(DEFAULT_ROUTE)])
+    }
+
+    pub fn with_author(mut self, author: Template) -> Self {
+        self.author = Some(author);
+        self
+    }
+
+    pub fn get_author(&self) -> Option<&Template> {
+        self.author.as_ref()
+    }
+
+    pub fn with_warn_after(mut self, warn_after: FixedPeriodInner) -> Self {
+        self.warn_after = Some(warn_after);
+        self
+    }
+
+    pub fn with_expected_duration(mut self, expected_duration: ExpectedDuration) -> Self {
+        self.expected_duration = Some(expected_duration);
+        self
+    }
+
+    pub fn get_expected_duration(&self) -> Option<&ExpectedDuration> {
+        self.expected_duration.as_ref()
+    }
+
+    pub fn with_overrun_level(mut self, overrun_level: Level) -> Self {
+        self.overrun_level = Some(overrun_level);
+        self
+    }
+
+    /// Creates the notification to send when a scheduled run is skipped because the job is still running,
+    /// if overruns should be notified.
+    /// running_since: when the current run started, skipped: how many runs have been skipped since then
+    pub fn create_overrun_message(&self, job_id: &JobDefinitionId, running_since: u64, skipped: u32,
+                                  author: &Template, now: u64) -> Option<Notification> {
+        let level = self.overrun_level.clone()?;
+        let running_for = format_duration(now.saturating_sub(running_since));
+        let slots = if skipped == 1 { "slot" } else { "slots" };
+        let title = format!("{job_id} still running after {running_for}, skipped {skipped} {slots}");
+        let detail = format!("{job_id} has been running for {running_for}, and does not allow parallel runs, \
+                              so {skipped} scheduled {} been skipped.", if skipped == 1 { "run has" } else { "runs have" });
+        Some(self.create_warning(job_id, level, title, detail, render_author(author, job_id, &running_for), now))
+    }
+
+    /// Creates the notification to send when the job has been running for longer than warn_after, if it has.
+    pub fn create_running_long_message(&self, job_id: &JobDefinitionId, started: u64,
+                                       author: &Template, now: u64) -> Option<Notification> {
+        let warn_after = self.warn_after.as_ref()?.as_seconds();
+        let running_for = now.saturating_sub(started);
+        if running_for < warn_after {
+            return None;
+        }
+        let running_for = format_duration(running_for);
+        let title = format!("{job_id} still running after {running_for}");
+        let detail = format!("{job_id} has been running for {running_for}, but is expected to finish within {}.",
+                             format_duration(warn_after));
+        Some(self.create_warning(job_id, Level::Warn, title, detail, render_author(author, job_id, &running_for), now))
+    }
+
+    /// Gets when the job should be warned about if it is still running, given when it started.
+    pub fn get_warn_time(&self, started: u64) -> Option<u64> {
+        self.warn_after.as_ref().map(|warn_after| started + warn_after.as_seconds())
+    }
+
+    /// Creates the notification to send when a completed run took much more or less time than expected, if it did.
+    /// previous: the state of the job before the run, whose history gives the usual duration.
+    pub fn create_duration_message(&self, job_id: &JobDefinitionId, duration: u64, previous: &JobState,
+                                   author: &Template, now: u64) -> Option<Notification> {
+        let expected = self.expected_duration.as_ref()?.check(duration, previous)?;
+        let took = format_duration(duration);
+        let title = format!("{job_id} took {took}, usually takes {}", format_duration(expected));
+        let detail = format!("{job_id} took {took} to run, which is unusually {} compared to the expected {}.",
+                             if duration > expected { "long" } else { "short" }, format_duration(expected));
+        Some(self.create_warning(job_id, Level::Warn, title, detail, render_author(author, job_id, &took), now))
+    }
+
+    /// Creates the notification to send when the job hasn't succeeded by its deadline.
+    /// running_since: when the job started running, if it is still running.
+    pub fn create_deadline_message(&self, job_id: &JobDefinitionId, missed: &PendingDeadline, state: &JobState,
+                                   running_since: Option<u64>, author: &Template, now: u64) -> Notification {
+        let title = format!("{job_id} has not succeeded by {}", format_time(missed.get_due(), "%H:%M"));
+        let reason = match (running_since, state.get_last_run()) {
+            (Some(running_since), _) => format!("It is still running, after {}.", format_duration(now.saturating_sub(running_since))),
+            (None, Some(last_run)) if last_run.get_started() >= missed.get_since() =>
+                format!("Its last run, at {}, {}.", format_time(last_run.get_started(), TIME_FORMAT), last_run.get_result().type_str()),
+            (None, _) => String::from("It has not run since then."),
+        };
+        let detail = format!("{job_id} must succeed by {}, but has not succeeded since {}. {reason}",
+                             format_time(missed.get_due(), TIME_FORMAT), format_time(missed.get_since(), TIME_FORMAT));
+        let late_by = format_duration(now.saturating_sub(missed.get_due()));
+        let level = self.get_level(ResultKind::Failed);
+        self.create_warning(job_id, level, title, detail, render_author(author, job_id, &late_by), now)
+    }
+
+    /// Creates a notification about the job which isn't about the result of a run.
+    fn create_warning(&self, job_id: &JobDefinitionId, level: Level, title: String, detail: String,
+                      author: Author, now: u64) -> Notification {
+        let message = Message::new(level.clone(), Some(title.clone()), MessageDetail::Raw(detail),
+                                   Some(self.component.clone()), author, (now * 1000) as i64);
+        Notification::new(job_id.clone(), ResultKind::Warning, level, title, message)
+    }
+
+    pub fn get_alert_policy(&self) -> AlertPolicy {
+        AlertPolicy::new(self.failure_threshold, self.recovery_threshold,
+                         self.renotify_interval.as_ref().map(|i| i.as_seconds()),
+                         self.flapping.clone())
+    }
+
+    pub fn with_report_if_changed(mut self, report_if_changed: bool) -> Self {
+        self.report_if_changed = report_if_changed;
+        self
+    }
+
+    /// Whether the stdout of the job needs to be kept to compare against the next run.
+    pub fn tracks_output(&self) -> bool {
+        self.report_if_changed || self.output_format == ProgramOutputFormat::Diff
+    }
+
+    pub fn with_summary(mut self, summary: Template) -> Self {
+        self.summary = Some(summary);
+        self
+    }
+
+    pub fn render_title(&self, context: &TemplateContext) -> String {
+        self.title.render(context)
+    }
+
+    pub fn render_summary(&self, context: &TemplateContext) -> Option<String> {
+        self.summary.as_ref().map(|summary| summary.render(context))
+    }
+
+    pub fn get_thresholds(&self) -> &BTreeMap<String, Threshold> {
+        &self.thresholds
+    }
+
+    /// Evaluates the metrics against their thresholds, giving the most severe level reached.
+    pub fn evaluate_thresholds(&self, metrics: &Metrics) -> ThresholdLevel {
+        metrics.iter()
+            .filter_map(|(name, value)| self.thresholds.get(name).map(|t| t.evaluate(*value)))
+            .max()
+            .unwrap_or(ThresholdLevel::Normal)
+    }
+
+    /// Creates the notification to send for the outcome of a job, if one should be sent.
+    /// alert: the job's alert after the outcome, and alert_event: what the outcome meant for it,
+    /// see [alert::evaluate](crate::alert::evaluate)
+    /// author: the template for the author of the message, see [Config::get_author](crate::config::Config::get_author)
+    pub fn create_message(&self, job_id: &JobDefinitionId, outcome: JobOutcome, alert: Option<&Alert>,
+                          alert_event: &AlertEvent, author: &Template) -> Option<Notification> {
+        let author = Author::parse(author.render(outcome.get_context()));
         let unix_timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
             .expect("Failed to get duration since unix epoch")
             .as_millis();
 
-        if let JobResult::Ok(_) = job_result {
-            if !self.report_if_success {
-                return None;
+        let report_change = self.report_if_changed && outcome.is_output_changed();
+        let (job_result, mut title) = outcome.into_parts();
+        let result_kind = job_result.kind();
+        let now = (unix_timestamp / 1000) as u64;
+        let notify = match (&self.notify_on, alert_event) {
+            (_, AlertEvent::Suppressed | AlertEvent::Flapping) => false,
+            (_, AlertEvent::Reminder | AlertEvent::FlappingStarted | AlertEvent::FlappingStopped) => true,
+            (NotifyOn::Always, AlertEvent::Ongoing) => self.renotify_interval.is_none(),
+            (NotifyOn::Always, AlertEvent::Raised | AlertEvent::Changed) => true,
+            (NotifyOn::Always, AlertEvent::Ok | AlertEvent::RecoveryPending | AlertEvent::Recovered { .. }) => self.report_if_success,
+            (NotifyOn::StateChange, AlertEvent::Raised | AlertEvent::Changed | AlertEvent::Recovered { .. }) => true,
+            (NotifyOn::StateChange, AlertEvent::Ok | AlertEvent::RecoveryPending | AlertEvent::Ongoing) => false,
+        };
+        if !notify && !report_change {
+            return None;
+        }
+
+        if let AlertEvent::Recovered { since } = alert_event {
+            title = format!("Recovered after {}: {title}", format_duration(now.saturating_sub(*since)));
+        }
+        if let (AlertEvent::Reminder, Some(alert)) = (alert_event, alert) {
+            title = format!("Ongoing for {}: {title}", format_duration(now.saturating_sub(alert.get_since())));
+        }
+        match alert_event {
+            AlertEvent::FlappingStarted => title = format!("Flapping: {title}"),
+            AlertEvent::FlappingStopped => title = format!("Stopped flapping: {title}"),
+            _ => {}
+        }
+
+        let mut level = self.get_level(result_kind);
+        if let (AlertEvent::FlappingStarted, JobResult::Ok(_)) = (alert_event, &job_result) {
+            level = Level::Warn;
+        }
+        // Only escalate messages about the problem, never lowering their level.
+        let unsuccessful = matches!(alert_event, AlertEvent::Raised | AlertEvent::Ongoing | AlertEvent::Reminder | AlertEvent::Changed);
+        if let (Some(escalation), Some(alert), true) = (&self.escalation, alert, unsuccessful) {
+            if now >= alert.get_since() + escalation.after.as_seconds() && severity(&escalation.level) > severity(&level) {
+                level = escalation.level.clone();
             }
         }
 
-        let level = match job_result {
-            JobResult::Ok(_) => Level::Info,
-            JobResult::Invalid(_) => Level::SelfError,
-            JobResult::Failed(_) => Level::Error,
-        };
+        let message = Message::new(level.clone(), Some(title.clone()), job_result.take_detail(),
+                                   Some(self.component.clone()), author,
+                                   unix_timestamp as i64);
+        Some(Notification::new(job_id.clone(), result_kind, level, title, message))
+    }
+}
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+fn format_time(unix_time: u64, format: &str) -> String {
+    match Local.timestamp_opt(unix_time as i64, 0).single() {
+        Some(time) => time.format(format).to_string(),
+        None => unix_time.to_string(),
+    }
+}
+
+/// Renders the author of a message about the job which isn't about the result of a run.
+fn render_author(author: &Template, job_id: &JobDefinitionId, duration: &str) -> Author {
+    let mut context = TemplateContext::new();
+    context.set(TemplateVariable::JobId, job_id);
+    context.set(TemplateVariable::Hostname, host::get_hostname());
+    context.set(TemplateVariable::Duration, duration);
+    Author::parse(author.render(&context))
+}
+
+/// The author of messages if none is configured, e.g. rnotifyd@db-02/backup-nightly
+pub const DEFAULT_AUTHOR: &str = "rnotifyd@{{hostname}}/{{job_id}}";
+
+/// The name of the route which uses the main rnotify config.
+pub const DEFAULT_ROUTE: &str = "default";
+
+fn default_alert_threshold() -> u32 {
+    1
+}
+
+/// The level to send messages about an alert at, once it has been ongoing for a given time.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Escalation {
+    after: FixedPeriodInner,
+    level: Level,
+}
+
+impl Escalation {
+    pub fn new(after: FixedPeriodInner, level: Level) -> Self {
+        Self {
+            after,
+            level,
+        }
+    }
+}
+
+/// How long a job is expected to take.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ExpectedDuration {
+    /// The expected duration, used until the job has enough successful runs to use their median duration instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<FixedPeriodInner>,
+    /// Runs taking more than this many times longer, or shorter, than expected are flagged.
+    #[serde(default = "default_duration_tolerance")]
+    tolerance: f64,
+}
+
+/// The number of successful runs needed to use their median duration as the expected duration.
+const MIN_RUNS_FOR_MEDIAN: usize = 5;
+/// Runs within this many seconds of the expected duration are never flagged, so that short jobs aren't noisy.
+const MIN_DURATION_DEVIATION: u64 = 60;
+
+fn default_duration_tolerance() -> f64 {
+    3.0
+}
+
+impl ExpectedDuration {
+    pub fn new(duration: Option<FixedPeriodInner>, tolerance: f64) -> Self {
+        Self {
+            duration,
+            tolerance,
+        }
+    }
+
+    pub fn get_tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    /// Checks a run's duration against the expected duration, giving the expected duration if it deviates too much.
+    pub fn check(&self, duration: u64, previous: &JobState) -> Option<u64> {
+        let expected = previous.get_median_duration(MIN_RUNS_FOR_MEDIAN)
+            .or_else(|| self.duration.as_ref().map(|d| d.as_seconds()))?;
+        if duration.abs_diff(expected) < MIN_DURATION_DEVIATION {
+            return None;
+        }
+        let (duration_f, expected_f) = (duration as f64, expected as f64);
+        if duration_f > expected_f * self.tolerance || duration_f * self.tolerance < expected_f {
+            return Some(expected);
+        }
+        None
+    }
+}
+
+/// When a job's results should be notified.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyOn {
+    /// Every failure, and every success if report_if_success is set.
+    #[default]
+    Always,
+    /// Only when an alert is raised, changes, or is resolved.
+    /// A success after a failure is reported as a recovery.
+    StateChange,
+}
+
+#[cfg(test)]
+mod test {
+    use rnotifylib::message::MessageDetail;
+    use crate::job_state::{JobStates, RunRecord};
+    use crate::metrics::Metrics;
+    use super::*;
+
+    fn outcome(kind: ResultKind) -> JobOutcome {
+        let result = JobResult::new(kind, MessageDetail::Raw("detail".to_owned()));
+        JobOutcome::new(result, "Title".to_owned(), Metrics::new(), None, false)
+    }
+
+    fn author() -> Template {
+        Template::parse(DEFAULT_AUTHOR).unwrap()
+    }
+
+    #[test]
+    fn test_state_change() {
+        let id = JobDefinitionId::try_new("ping".to_owned()).unwrap();
+        let definition = NotifyDefinition::new(Template::parse("Title").unwrap(), Component::from("ping"),
+                                               false, ProgramOutputFormat::SimpleIfSuccess)
+            .with_notify_on(NotifyOn::StateChange);
+
+        assert!(definition.create_message(&id, outcome(ResultKind::Ok), None, &AlertEvent::Ok, &author()).is_none());
+        assert!(definition.create_message(&id, outcome(ResultKind::Failed), None, &AlertEvent::Raised, &author()).is_some());
+        assert!(definition.create_message(&id, outcome(ResultKind::Failed), None, &AlertEvent::Ongoing, &author()).is_none());
+        assert!(definition.create_message(&id, outcome(ResultKind::Failed), None, &AlertEvent::Reminder, &author()).is_some());
+        assert!(definition.create_message(&id, outcome(ResultKind::Ok), None, &AlertEvent::Recovered { since: 0 }, &author()).is_some());
+    }
+
+    #[test]
+    fn test_overrun() {
+        let id = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        let definition = NotifyDefinition::new(Template::parse("Title").unwrap(), Component::from("backup"),
+                                               false, ProgramOutputFormat::SimpleIfSuccess);
+        assert!(definition.create_overrun_message(&id, 0, 1, &author(), 3600).is_none());
+
+        let definition = definition.with_overrun_level(Level::Error);
+        let notification = definition.create_overrun_message(&id, 0, 1, &author(), 3600).unwrap();
+        assert_eq!(&Level::Error, notification.get_level());
+        assert!(notification.get_title().ends_with("skipped 1 slot"), "{}", notification.get_title());
+        let notification = definition.create_overrun_message(&id, 0, 3, &author(), 3600).unwrap();
+        assert!(notification.get_title().ends_with("skipped 3 slots"), "{}", notification.get_title());
+    }
+
+    #[test]
+    fn test_expected_duration() {
+        let expected = ExpectedDuration::new(Some(FixedPeriodInner::new(0, 10, 0)), 3.0);
+        let mut previous = JobState::default();
+        assert_eq!(None, expected.check(900, &previous));
+        assert_eq!(Some(600), expected.check(1801, &previous));
+        assert_eq!(Some(600), expected.check(100, &previous));
+
+        // Once there is enough history, the median is used instead.
+        let mut states = JobStates::default();
+        let id = JobDefinitionId::try_new("backup-nightly".to_owned()).unwrap();
+        for duration in [3000, 3600, 3300, 3500, 3400] {
+            states.record(id.clone(), RunRecord::new(0, ResultKind::Ok, Metrics::new()).with_duration(duration));
+        }
+        previous = states.get_or_default(&id);
+        assert_eq!(None, expected.check(1801, &previous));
+        assert_eq!(Some(3400), expected.check(12000, &previous));
+    }
+
+    #[test]
+    fn test_level_override() {
+        let id = JobDefinitionId::try_new("ping".to_owned()).unwrap();
+        let definition = NotifyDefinition::new(Template::parse("Title").unwrap(), Component::from("ping"),
+                                               false, ProgramOutputFormat::SimpleIfSuccess)
+            .with_level(ResultKind::Failed, Level::Warn);
 
-        Some(Message::new(level, Some(self.title.clone()), job_result.take_detail(),
-                     Some(self.component.clone()), author,
-                     unix_timestamp as i64))
+        let notification = definition.create_message(&id, outcome(ResultKind::Failed), None, &AlertEvent::Raised, &author()).unwrap();
+        assert_eq!(&Level::Warn, notification.get_level());
+        let notification = definition.create_message(&id, outcome(ResultKind::Invalid), None, &AlertEvent::Raised, &author()).unwrap();
+        assert_eq!(&Level::SelfError, notification.get_level());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_escalation() {
+        let id = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        let definition = |level: Level| NotifyDefinition::new(Template::parse("Title").unwrap(), Component::from("backup"),
+                                                              true, ProgramOutputFormat::SimpleIfSuccess)
+            .with_escalation(Escalation::new(FixedPeriodInner::new(1, 0, 0), level));
+        let update = crate::alert::evaluate(&JobState::default(), ResultKind::Failed, 0, &AlertPolicy::new(1, 2, None, None));
+        let alert = update.get_alert();
+
+        let notification = definition(Level::Error).create_message(&id, outcome(ResultKind::Warning), alert, &AlertEvent::Ongoing, &author()).unwrap();
+        assert_eq!(&Level::Error, notification.get_level());
+
+        // A successful run while the alert waits to recover isn't escalated.
+        let notification = definition(Level::Error).create_message(&id, outcome(ResultKind::Ok), alert, &AlertEvent::RecoveryPending, &author()).unwrap();
+        assert_eq!(&Level::Info, notification.get_level());
+
+        // Escalation never lowers the level.
+        let notification = definition(Level::Warn).create_message(&id, outcome(ResultKind::Invalid), alert, &AlertEvent::Ongoing, &author()).unwrap();
+        assert_eq!(&Level::SelfError, notification.get_level());
+    }
+}
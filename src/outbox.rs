@@ -0,0 +1,285 @@
+use std::cmp::min;
+use std::fs;
+use std::path::PathBuf;
+use log::warn;
+use rnotifylib::message::Message;
+use serde::{Serialize, Deserialize};
+use crate::frequency::FixedPeriodInner;
+use crate::notify_definition::DEFAULT_ROUTE;
+
+/// How long to wait before the first retry, doubling for each retry after.
+const FIRST_RETRY_DELAY: u64 = 30;
+
+/// How messages that failed to send are retried.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OutboxDefinition {
+    /// How long to keep trying to send a message before giving up on it.
+    #[serde(default = "default_ttl")]
+    ttl: FixedPeriodInner,
+    /// The longest time to wait between retries.
+    #[serde(default = "default_max_retry_interval")]
+    max_retry_interval: FixedPeriodInner,
+}
+
+fn default_ttl() -> FixedPeriodInner {
+    FixedPeriodInner::new(24, 0, 0)
+}
+
+fn default_max_retry_interval() -> FixedPeriodInner {
+    FixedPeriodInner::new(1, 0, 0)
+}
+
+impl Default for OutboxDefinition {
+    fn default() -> Self {
+        Self {
+            ttl: default_ttl(),
+            max_retry_interval: default_max_retry_interval(),
+        }
+    }
+}
+
+impl OutboxDefinition {
+    pub fn new(ttl: FixedPeriodInner, max_retry_interval: FixedPeriodInner) -> Self {
+        Self {
+            ttl,
+            max_retry_interval,
+        }
+    }
+
+    /// The delay before retrying a message that has failed to send the given number of times.
+    fn retry_delay(&self, failures: u32) -> u64 {
+        let backoff = FIRST_RETRY_DELAY.saturating_mul(1 << min(failures.saturating_sub(1), 20));
+        min(backoff, self.max_retry_interval.as_seconds())
+    }
+}
+
+fn default_route() -> String {
+    String::from(DEFAULT_ROUTE)
+}
+
+/// A message waiting to be sent to rnotify.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OutboxEntry {
+    id: u64,
+    /// What the message is about, e.g. the job id, for logging.
+    name: String,
+    /// The name of the rnotify config to send the message through.
+    #[serde(default = "default_route")]
+    route: String,
+    queued: u64,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default)]
+    next_attempt: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    message: Message,
+    /// Whether the message has been written to the fallback sinks, which is done once when it fails to send.
+    #[serde(default)]
+    fallback_written: bool,
+    /// Whether the message is being sent, so shouldn't be retried until that attempt finishes.
+    #[serde(skip)]
+    sending: bool,
+}
+
+impl OutboxEntry {
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_route(&self) -> &str {
+        &self.route
+    }
+
+    pub fn get_queued(&self) -> u64 {
+        self.queued
+    }
+
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn get_last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    pub fn get_message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn is_fallback_written(&self) -> bool {
+        self.fallback_written
+    }
+
+    pub fn into_message(self) -> Message {
+        self.message
+    }
+}
+
+/// Messages which have not yet been sent, kept on disk so that they are not lost if sending fails
+/// or rnotifyd is restarted.
+/// Messages are sent at least once: a message is retried if sending it to any destination in its rnotify
+/// config fails, so the destinations that succeeded get it again. If rnotifyd is restarted while a message
+/// is being sent, it is sent again too.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Outbox {
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    entries: Vec<OutboxEntry>,
+}
+
+impl Outbox {
+    /// Adds a message, to be sent immediately through the given route, giving its id.
+    pub fn push(&mut self, name: String, route: String, message: Message, now: u64) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(OutboxEntry {
+            id,
+            name,
+            route,
+            queued: now,
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+            message,
+            fallback_written: false,
+            sending: false,
+        });
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&OutboxEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The ids of the messages which are due to be sent, and aren't already being sent.
+    pub fn get_due(&self, now: u64) -> Vec<u64> {
+        self.entries.iter()
+            .filter(|e| !e.sending && e.next_attempt <= now)
+            .map(|e| e.id)
+            .collect()
+    }
+
+    /// The unix time that the next message is due to be sent.
+    pub fn get_next_attempt(&self) -> Option<u64> {
+        self.entries.iter()
+            .filter(|e| !e.sending)
+            .map(|e| e.next_attempt)
+            .min()
+    }
+
+    /// Records that a message is being sent, so it isn't sent again until the attempt finishes.
+    /// Gives None if it is already being sent.
+    pub fn start_attempt(&mut self, id: u64) -> Option<&OutboxEntry> {
+        let entry = self.entries.iter_mut().find(|e| e.id == id && !e.sending)?;
+        entry.attempts += 1;
+        entry.sending = true;
+        Some(entry)
+    }
+
+    /// Records that the message has been written to the fallback sinks, giving whether it needed to be.
+    pub fn mark_fallback_written(&mut self, id: u64) -> bool {
+        match self.entries.iter_mut().find(|e| e.id == id) {
+            Some(entry) if !entry.fallback_written => {
+                entry.fallback_written = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn delivered(&mut self, id: u64) -> Option<OutboxEntry> {
+        let index = self.entries.iter().position(|e| e.id == id)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Records that sending a message failed, giving how long until it will be retried.
+    pub fn failed(&mut self, id: u64, error: String, now: u64, definition: &OutboxDefinition) -> Option<u64> {
+        let entry = self.entries.iter_mut().find(|e| e.id == id)?;
+        let delay = definition.retry_delay(entry.attempts);
+        entry.sending = false;
+        entry.next_attempt = now + delay;
+        entry.last_error = Some(error);
+        Some(delay)
+    }
+
+    /// Removes the messages which have been waiting longer than the TTL, giving them.
+    pub fn take_expired(&mut self, now: u64, definition: &OutboxDefinition) -> Vec<OutboxEntry> {
+        let ttl = definition.ttl.as_seconds();
+        let (expired, waiting) = std::mem::take(&mut self.entries).into_iter()
+            .partition(|e| now >= e.queued + ttl);
+        self.entries = waiting;
+        expired
+    }
+
+    pub fn read_from_string(s: &str) -> Result<Outbox, String> {
+        serde_yaml::from_str(s).map_err(|err| format!("Error parsing outbox: {err}"))
+    }
+
+    pub fn write_to_string(&self) -> String {
+        serde_yaml::to_string(self).expect("Failed to serialize outbox")
+    }
+}
+
+pub fn read_outbox(path: &PathBuf) -> Outbox {
+    if !path.exists() {
+        return Outbox::default();
+    }
+    let outbox_str = fs::read_to_string(path).expect("Failed to read outbox");
+    match Outbox::read_from_string(&outbox_str) {
+        Ok(outbox) => outbox,
+        Err(err) => {
+            warn!("{err}, starting with an empty outbox.");
+            Outbox::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rnotifylib::message::author::Author;
+    use rnotifylib::message::{Level, MessageDetail};
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let definition = OutboxDefinition::new(FixedPeriodInner::new(1, 0, 0), FixedPeriodInner::new(0, 5, 0));
+        let message = Message::new(Level::Info, None, MessageDetail::Raw("hi".to_owned()), None,
+                                   Author::parse("test".to_owned()), 0);
+        let mut outbox = Outbox::default();
+        let id = outbox.push("ping".to_owned(), DEFAULT_ROUTE.to_owned(), message, 1000);
+        assert_eq!(vec![id], outbox.get_due(1000));
+
+        let mut delays = vec![];
+        for _ in 0..5 {
+            outbox.start_attempt(id);
+            assert!(outbox.start_attempt(id).is_none());
+            assert!(outbox.get_due(5000).is_empty());
+            delays.push(outbox.failed(id, "offline".to_owned(), 1000, &definition).unwrap());
+        }
+        assert_eq!(vec![30, 60, 120, 240, 300], delays);
+        assert!(outbox.get_due(1299).is_empty());
+        assert!(outbox.mark_fallback_written(id));
+        assert!(!outbox.mark_fallback_written(id));
+
+        let parsed = Outbox::read_from_string(&outbox.write_to_string()).unwrap();
+        assert_eq!(outbox, parsed);
+
+        assert!(outbox.take_expired(4599, &definition).is_empty());
+        assert_eq!(1, outbox.take_expired(4600, &definition).len());
+        assert!(outbox.is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+use chrono::{Local, TimeZone};
+use rnotifylib::message::formatted_detail::FormattedString;
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
+use serde::{Serialize, Deserialize};
+use crate::action;
+use crate::config::JobDefinitionId;
+use crate::duration_format::format_duration;
+use crate::frequency::{FixedPeriodInner, Frequency};
+use crate::job_result::{JobOutcome, JobResult};
+use crate::job_state::{JobState, ResultKind};
+use crate::metrics::Metrics;
+use crate::notify_definition::NotifyDefinition;
+use crate::redact::Redactor;
+use crate::template::{TemplateContext, TemplateVariable};
+
+/// The maximum amount of a ping's body included in the notification.
+const MAX_BODY_LENGTH: usize = 10_000;
+
+/// A job which runs nothing, and is instead pinged over HTTP by something else, such as a cron job on another system.
+/// If it isn't pinged within its frequency plus the grace period, it is treated as having failed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PassiveDefinition {
+    /// How long after the job is expected to ping to wait before alerting.
+    #[serde(default = "default_grace")]
+    grace: FixedPeriodInner,
+}
+
+impl PassiveDefinition {
+    pub fn new(grace: FixedPeriodInner) -> Self {
+        Self {
+            grace,
+        }
+    }
+
+    pub fn get_grace(&self) -> u64 {
+        self.grace.as_seconds()
+    }
+
+    /// Gets the time by which the job must next ping, given when it last pinged.
+    /// A ping up to the grace period before a scheduled time counts for that time,
+    /// and a job which has never pinged isn't expected until the first scheduled time after now.
+    pub fn get_deadline(&self, frequency: &Frequency, last_ping: Option<u64>, now: u64) -> u64 {
+        let grace = self.get_grace();
+        let expected = match (frequency, last_ping) {
+            (Frequency::FixedPeriod(period), last_ping) => last_ping.unwrap_or(now) + period.as_seconds(),
+            (_, Some(last_ping)) => frequency.next_after(last_ping + grace),
+            (_, None) => frequency.next_after(now),
+        };
+        expected + grace
+    }
+}
+
+impl Default for PassiveDefinition {
+    fn default() -> Self {
+        Self::new(default_grace())
+    }
+}
+
+fn default_grace() -> FixedPeriodInner {
+    FixedPeriodInner::new(0, 5, 0)
+}
+
+/// What a ping to a passive job reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingKind {
+    /// The job ran successfully, POST /ping/job-id
+    Success,
+    /// The job has started, POST /ping/job-id/start
+    Start,
+    /// The job failed, POST /ping/job-id/fail
+    Fail,
+}
+
+impl PingKind {
+    /// Parses the part of the path after the job id.
+    pub fn parse(s: Option<&str>) -> Option<Self> {
+        match s {
+            None | Some("") => Some(PingKind::Success),
+            Some("start") => Some(PingKind::Start),
+            Some("fail") => Some(PingKind::Fail),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Creates the outcome of a passive job from a ping that reported it finished.
+/// body: the body of the ping, which is shown as the job's output.
+pub fn create_ping_outcome(job_id: &JobDefinitionId, notify_definition: &NotifyDefinition, redactor: &Redactor,
+                           previous: &JobState, kind: ResultKind, body: &str, duration: u64) -> JobOutcome {
+    let mut context = action::create_template_context(job_id, previous, kind, Duration::from_secs(duration));
+    let body = redactor.redact(truncate(body.trim()));
+    context.set(TemplateVariable::StdoutFirstLine, body.lines().next().unwrap_or_default());
+    let mut raw = if body.is_empty() { format!("{job_id} pinged as {}.", kind.type_str()) } else { body };
+    if let Some(summary) = notify_definition.render_summary(&context) {
+        raw = format!("{summary}\n{raw}");
+    }
+    create_outcome(notify_definition, context, kind, raw, duration)
+}
+
+/// Creates the outcome of a passive job which wasn't pinged by when it was expected to be.
+/// running_since: when the job last pinged that it started, if it didn't ping after that.
+pub fn create_missed_outcome(job_id: &JobDefinitionId, notify_definition: &NotifyDefinition, previous: &JobState,
+                             expected: u64, running_since: Option<u64>, now: u64) -> JobOutcome {
+    let context = action::create_template_context(job_id, previous, ResultKind::Failed, Duration::ZERO);
+    let expected_at = format_time(expected);
+    let mut raw = match running_since {
+        Some(started) => format!("{job_id} was expected to ping by {expected_at}. It pinged that it started {} ago, but hasn't pinged since.",
+                                 format_duration(now.saturating_sub(started))),
+        None => format!("{job_id} was expected to ping by {expected_at}, but hasn't pinged."),
+    };
+    if let Some(summary) = notify_definition.render_summary(&context) {
+        raw = format!("{summary}\n{raw}");
+    }
+    create_outcome(notify_definition, context, ResultKind::Failed, raw, 0)
+}
+
+fn create_outcome(notify_definition: &NotifyDefinition, context: TemplateContext, kind: ResultKind,
+                  raw: String, duration: u64) -> JobOutcome {
+    let title = notify_definition.render_title(&context);
+    let detail = MessageDetailBuilder::with_raw(raw.clone())
+        .text(vec![FormattedString::plain(raw)])
+        .build();
+    JobOutcome::new(JobResult::new(kind, detail), title, Metrics::new(), None, false)
+        .with_context(context)
+        .with_duration(duration)
+}
+
+fn truncate(s: &str) -> &str {
+    match s.char_indices().nth(MAX_BODY_LENGTH) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+fn format_time(unix_time: u64) -> String {
+    match Local.timestamp_opt(unix_time as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => unix_time.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_ping_kind() {
+        assert_eq!(Some(PingKind::Success), PingKind::parse(None));
+        assert_eq!(Some(PingKind::Start), PingKind::parse(Some("start")));
+        assert_eq!(Some(PingKind::Fail), PingKind::parse(Some("fail")));
+        assert_eq!(None, PingKind::parse(Some("stop")));
+    }
+
+    #[test]
+    fn test_get_deadline() {
+        let passive = PassiveDefinition::new(FixedPeriodInner::new(0, 30, 0));
+        let daily = Frequency::Daily { time: chrono::NaiveTime::from_hms_opt(2, 0, 0).unwrap() };
+        let at = |d, h, m| Local.with_ymd_and_hms(2022, 12, d, h, m, 0).unwrap().timestamp() as u64;
+
+        // Pinging a little early still counts for that day.
+        assert_eq!(at(7, 2, 30), passive.get_deadline(&daily, Some(at(6, 1, 50)), at(6, 1, 50)));
+        assert_eq!(at(7, 2, 30), passive.get_deadline(&daily, Some(at(6, 2, 5)), at(6, 3, 0)));
+        assert_eq!(at(7, 2, 30), passive.get_deadline(&daily, None, at(6, 3, 0)));
+
+        let hourly = Frequency::FixedPeriod(FixedPeriodInner::new(1, 0, 0));
+        assert_eq!(at(6, 3, 40), passive.get_deadline(&hourly, Some(at(6, 2, 10)), at(6, 2, 10)));
+    }
+}
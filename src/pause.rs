@@ -0,0 +1,32 @@
+use serde::{Serialize, Deserialize};
+
+/// A job being paused at runtime, so that it isn't run on its schedule.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct JobPause {
+    since: u64,
+    /// When the job is automatically resumed, or None if it is paused until it is resumed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    until: Option<u64>,
+}
+
+impl JobPause {
+    pub fn new(since: u64, until: Option<u64>) -> Self {
+        Self {
+            since,
+            until,
+        }
+    }
+
+    pub fn get_since(&self) -> u64 {
+        self.since
+    }
+
+    pub fn get_until(&self) -> Option<u64> {
+        self.until
+    }
+
+    /// Whether the pause has ended, so the job should be resumed.
+    pub fn has_ended(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+}
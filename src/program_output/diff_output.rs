@@ -0,0 +1,64 @@
+use rnotifylib::message::formatted_detail::{FormattedString, Style};
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
+use similar::TextDiff;
+use crate::program_output::{to_detail_verbose, ProgramOutput};
+
+const MAX_DIFF_LEN: usize = 1000;
+
+/// Converts the program output into a unified diff against the stdout of the previous run.
+pub fn to_detail_from_diff(mut output: ProgramOutput, previous_stdout: Option<&str>) -> MessageDetailBuilder {
+    if !output.is_success() {
+        output.trim_to(500);
+        return to_detail_verbose(&output);
+    }
+
+    let mut builder = match previous_stdout {
+        None => {
+            output.trim_to(500);
+            output.new_builder(output.get_stdout().to_owned())
+                .text(vec![FormattedString::plain("No previous output to compare against")])
+                .section("Stdout", |s| {
+                    s.append_styled(output.get_stdout(), Style::Monospace);
+                })
+        }
+        Some(previous) if previous == output.get_stdout() => {
+            output.new_builder("Output unchanged".to_owned())
+                .text(vec![FormattedString::plain("Output unchanged since the previous run")])
+        }
+        Some(previous) => {
+            let mut diff = TextDiff::from_lines(previous, output.get_stdout())
+                .unified_diff()
+                .context_radius(3)
+                .header("previous", "current")
+                .to_string();
+            truncate_end(&mut diff, MAX_DIFF_LEN);
+
+            output.new_builder(diff.clone())
+                .text(vec![FormattedString::plain("Output changed since the previous run")])
+                .section("Diff", |s| {
+                    s.append_styled(diff, Style::Monospace);
+                })
+        }
+    };
+
+    output.trim_to(500);
+    if !output.get_stderr().is_empty() {
+        builder = builder.section("Stderr", |s| {
+            s.append_styled(output.get_stderr(), Style::Monospace);
+        });
+    }
+    builder
+}
+
+/// Keeps the start of the string, as the first changes in a diff are the most relevant.
+fn truncate_end(s: &mut String, max_len: usize) {
+    if s.len() <= max_len {
+        return;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+    s.push_str("...");
+}
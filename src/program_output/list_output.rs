@@ -2,11 +2,10 @@ use std::str::FromStr;
 use log::error;
 use rnotifylib::message::formatted_detail::{FormattedString, Style};
 use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
-use rnotifylib::message::MessageDetail;
 use crate::program_output::ProgramOutput;
 
 /// Converts the program output into a list of failures and successes.
-pub fn to_detail_from_list(output: &ProgramOutput) -> MessageDetail {
+pub fn to_detail_from_list(output: &ProgramOutput) -> MessageDetailBuilder {
     let mut successful_components = vec![];
     let mut failed_components = vec![];
 
@@ -37,7 +36,7 @@ pub fn to_detail_from_list(output: &ProgramOutput) -> MessageDetail {
         }
     }
 
-    let mut builder = MessageDetailBuilder::with_raw(output.get_stdout().to_string());
+    let mut builder = output.new_builder(output.get_stdout().to_string());
 
     builder = builder.section("Failed components", |s| {
         if failed_components.is_empty() {
@@ -71,7 +70,7 @@ pub fn to_detail_from_list(output: &ProgramOutput) -> MessageDetail {
             s.append_styled(output.get_stderr(), Style::Monospace);
         });
     }
-    builder.build()
+    builder
 }
 
 enum ComponentResult {
@@ -91,13 +90,12 @@ impl FromStr for ComponentResult {
     }
 }
 
-fn generate_invalid_format_message(output: &ProgramOutput, problem_line_num: usize) -> MessageDetail {
-    MessageDetailBuilder::with_raw(format!("Output from program did not conform to output format. Encountered first issue on line {}", problem_line_num))
+fn generate_invalid_format_message(output: &ProgramOutput, problem_line_num: usize) -> MessageDetailBuilder {
+    output.new_builder(format!("Output from program did not conform to output format. Encountered first issue on line {}", problem_line_num))
         .section("Received stdout", |s| {
             s.append_styled(output.get_stdout(), Style::Monospace);
         })
         .section("Stderr (Not parsed)", |s| {
             s.append_styled(output.get_stderr(), Style::Monospace);
         })
-        .build()
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+use chrono::{Local, TimeZone};
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
+use crate::duration_format::format_duration;
+use crate::redact::Redactor;
+
+/// Context about a run of a job, so that whoever reads the message knows what happened
+/// without having to log in to the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunMetadata {
+    hostname: String,
+    command: String,
+    started: u64,
+    duration: u64,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    attempt: u32,
+    last_success: Option<u64>,
+    next_run: Option<u64>,
+}
+
+impl RunMetadata {
+    /// started, last_success and next_run are unix times, duration is in seconds.
+    pub fn new(hostname: String, command: String, started: u64, duration: u64, attempt: u32,
+               last_success: Option<u64>, next_run: Option<u64>) -> Self {
+        Self {
+            hostname,
+            command,
+            started,
+            duration,
+            exit_code: None,
+            signal: None,
+            attempt,
+            last_success,
+            next_run,
+        }
+    }
+
+    pub fn with_exit(mut self, exit_code: i32, signal: Option<i32>) -> Self {
+        self.exit_code = Some(exit_code);
+        self.signal = signal;
+        self
+    }
+
+    /// Removes secrets from the command.
+    pub fn redact(&mut self, redactor: &Redactor) {
+        self.command = redactor.redact(&self.command);
+    }
+}
+
+/// Adds a section with the host, timing, exit status and schedule of the run.
+pub fn append_metadata_section(builder: MessageDetailBuilder, metadata: &RunMetadata, now: u64) -> MessageDetailBuilder {
+    let lines = describe(metadata, now);
+    builder.section("Run", |s| {
+        s.append_plain(lines.join("\n"));
+    })
+}
+
+fn describe(metadata: &RunMetadata, now: u64) -> Vec<String> {
+    let mut lines = vec![
+        format!("Host: {}", metadata.hostname),
+        format!("Command: {}", metadata.command),
+        format!("Started: {}", format_time(metadata.started)),
+        format!("Duration: {}", format_duration(metadata.duration)),
+    ];
+    match (metadata.signal, metadata.exit_code) {
+        (Some(signal), _) => lines.push(format!("Killed by signal: {signal}")),
+        (None, Some(exit_code)) => lines.push(format!("Exit code: {exit_code}")),
+        (None, None) => {}
+    }
+    lines.push(format!("Attempt: {}", metadata.attempt));
+    lines.push(match metadata.last_success {
+        Some(last_success) => format!("Last success: {} ({} ago)", format_time(last_success),
+                                      format_duration(now.saturating_sub(last_success))),
+        None => String::from("Last success: never"),
+    });
+    if let Some(next_run) = metadata.next_run {
+        lines.push(format!("Next run: {}", format_time(next_run)));
+    }
+    lines
+}
+
+fn format_time(unix_time: u64) -> String {
+    match Local.timestamp_opt(unix_time as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+        None => unix_time.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::redact::RedactionRules;
+    use super::*;
+
+    fn metadata(last_success: Option<u64>, next_run: Option<u64>) -> RunMetadata {
+        RunMetadata::new("db-02".to_owned(), "./backup.sh".to_owned(), 1000, 90, 2, last_success, next_run)
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let lines = describe(&metadata(None, None).with_exit(137, Some(9)), 1100);
+        assert!(lines.contains(&"Killed by signal: 9".to_owned()));
+        assert!(!lines.iter().any(|line| line.starts_with("Exit code")));
+
+        let lines = describe(&metadata(None, None).with_exit(1, None), 1100);
+        assert!(lines.contains(&"Exit code: 1".to_owned()));
+        assert!(!lines.iter().any(|line| line.starts_with("Killed by signal")));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let lines = describe(&metadata(None, None), 1100);
+        assert!(lines.contains(&"Last success: never".to_owned()));
+        assert!(!lines.iter().any(|line| line.starts_with("Next run")));
+
+        let lines = describe(&metadata(Some(400), Some(2000)), 1000);
+        assert!(lines.iter().any(|line| line.starts_with("Last success: ") && line.ends_with("(10m0s ago)")));
+        assert!(lines.iter().any(|line| line.starts_with("Next run: ")));
+    }
+
+    #[test]
+    fn test_redact_command() {
+        let rules: RedactionRules = serde_yaml::from_str("patterns: ['token=\\w+']").unwrap();
+        let mut metadata = RunMetadata::new("db-02".to_owned(), "./backup.sh token=abc123".to_owned(), 1000, 90, 1, None, None);
+        metadata.redact(&Redactor::new(&[&rules]));
+        assert!(describe(&metadata, 1100).contains(&"Command: ./backup.sh [REDACTED]".to_owned()));
+    }
+}
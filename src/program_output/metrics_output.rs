@@ -0,0 +1,32 @@
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
+use crate::duration_format::format_duration;
+use crate::job_state::JobState;
+use crate::metrics::{Metrics, ThresholdLevel};
+use crate::notify_definition::NotifyDefinition;
+
+/// Adds a section listing each metric, whether it crossed its threshold,
+/// and the value it had on the previous run that reported it.
+pub fn append_metrics_section(builder: MessageDetailBuilder, metrics: &Metrics, notify_definition: &NotifyDefinition,
+                              previous: &JobState, now: u64) -> MessageDetailBuilder {
+    builder.section("Metrics", |s| {
+        for (i, (name, value)) in metrics.iter().enumerate() {
+            if i != 0 {
+                s.append_plain("\n");
+            }
+            let mut line = format!("- {name}: {value}");
+
+            let level = notify_definition.get_thresholds().get(name)
+                .map(|threshold| threshold.evaluate(*value))
+                .unwrap_or(ThresholdLevel::Normal);
+            if level != ThresholdLevel::Normal {
+                line.push_str(&format!(" [{}]", level.type_str()));
+            }
+
+            if let Some((previous_value, previous_time)) = previous.get_previous_metric(name) {
+                let ago = format_duration(now.saturating_sub(previous_time));
+                line.push_str(&format!(" (was {previous_value} {ago} ago)"));
+            }
+            s.append_plain(line);
+        }
+    })
+}
@@ -2,14 +2,32 @@ use rnotifylib::message::formatted_detail::{FormattedString, Style};
 use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
 use rnotifylib::message::MessageDetail;
 use crate::action::ProgramOutputFormat;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::redact::Redactor;
 
 mod list_output;
+mod metrics_output;
+mod metadata_output;
+mod diff_output;
+mod sanitize;
+
+pub use metrics_output::append_metrics_section;
+pub use metadata_output::{append_metadata_section, RunMetadata};
+pub use sanitize::sanitize;
 
 #[derive(Debug)]
 pub struct ProgramOutput {
     std_out: String,
     std_err: String,
     exit_code: i32,
+    /// The signal that killed the program, if it was killed by one.
+    signal: Option<i32>,
+    /// Text to show at the start of the detail.
+    summary: Option<String>,
+    /// Context about the run to show after the summary, before the output,
+    /// and the unix time to describe it relative to.
+    metadata: Option<(RunMetadata, u64)>,
 }
 
 impl ProgramOutput {
@@ -17,7 +35,38 @@ impl ProgramOutput {
         Self {
             std_out,
             std_err,
-            exit_code
+            exit_code,
+            signal: None,
+            summary: None,
+            metadata: None,
+        }
+    }
+
+    pub fn with_signal(mut self, signal: Option<i32>) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    pub fn set_summary(&mut self, summary: Option<String>) {
+        self.summary = summary;
+    }
+
+    pub fn set_metadata(&mut self, metadata: RunMetadata, now: u64) {
+        self.metadata = Some((metadata, now));
+    }
+
+    /// Creates a builder for the detail of this output, starting with the summary and the metadata, if there are any.
+    fn new_builder(&self, raw: String) -> MessageDetailBuilder {
+        let builder = match &self.summary {
+            None => MessageDetailBuilder::with_raw(raw),
+            Some(summary) => {
+                MessageDetailBuilder::with_raw(format!("{summary}\n{raw}"))
+                    .text(vec![FormattedString::plain(summary)])
+            }
+        };
+        match &self.metadata {
+            Some((metadata, now)) => append_metadata_section(builder, metadata, *now),
+            None => builder,
         }
     }
 
@@ -43,6 +92,12 @@ impl ProgramOutput {
     }
 
 
+    /// Removes secrets from the output.
+    pub fn redact(&mut self, redactor: &Redactor) {
+        self.std_out = redactor.redact(&self.std_out);
+        self.std_err = redactor.redact(&self.std_err);
+    }
+
     pub fn get_stdout(&self) -> &str {
         &self.std_out
     }
@@ -51,44 +106,72 @@ impl ProgramOutput {
         &self.std_err
     }
 
+    /// Gets the first non-empty line of stdout.
+    pub fn get_first_line(&self) -> &str {
+        self.std_out.lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+            .unwrap_or("")
+    }
+
     pub fn get_exit_code(&self) -> i32 {
         self.exit_code
     }
 
+    pub fn get_signal(&self) -> Option<i32> {
+        self.signal
+    }
+
     pub fn is_success(&self) -> bool {
         self.exit_code == 0
     }
 
-    pub fn to_detail(mut self, format: &ProgramOutputFormat) -> MessageDetail {
+    pub fn get_metrics(&self) -> Metrics {
+        metrics::extract_metrics(&self.std_out)
+    }
+
+    pub fn to_detail(self, format: &ProgramOutputFormat) -> MessageDetail {
+        self.to_detail_builder(format, None).build()
+    }
+
+    /// Converts the output into a builder, so that further sections can be added to it.
+    /// previous_stdout: the stdout of the previous run, used by [ProgramOutputFormat::Diff]
+    pub fn to_detail_builder(mut self, format: &ProgramOutputFormat, previous_stdout: Option<&str>) -> MessageDetailBuilder {
+        if let ProgramOutputFormat::Diff = format {
+            return diff_output::to_detail_from_diff(self, previous_stdout);
+        }
         self.trim_to(500);
         let suc = self.is_success();
         match (format, suc) {
-            (ProgramOutputFormat::SimpleIfSuccess, true) => MessageDetail::Raw("Program Succeeded".to_owned()),
+            (ProgramOutputFormat::SimpleIfSuccess, true) => {
+                self.new_builder("Program Succeeded".to_owned())
+                    .text(vec![FormattedString::plain("Program Succeeded")])
+            },
             (ProgramOutputFormat::SimpleIfSuccess, false) => to_detail_verbose(&self),
 
             (ProgramOutputFormat::StdoutIfSuccess, true) => {
-                MessageDetailBuilder::new()
+                self.new_builder(self.std_out.clone())
                     .text(vec![FormattedString::plain("Program Succeeded")])
                     .section("Stdout", |section| {
                         section.append_styled(self.std_out, Style::Monospace);
                     })
-                    .build()
             },
             (ProgramOutputFormat::StdoutIfSuccess, false) => to_detail_verbose(&self),
             (ProgramOutputFormat::AlwaysDetailed, _) => to_detail_verbose(&self),
             (ProgramOutputFormat::List, _) => list_output::to_detail_from_list(&self),
+            (ProgramOutputFormat::Diff, _) => unreachable!("Diff output is handled above"),
         }
     }
 }
 
-fn to_detail_verbose(output: &ProgramOutput) -> MessageDetail {
+fn to_detail_verbose(output: &ProgramOutput) -> MessageDetailBuilder {
     let success = output.is_success();
     let raw = format!("{:?}", output);
 
     let exit_code_str = format!("exit code {:?}", output.get_exit_code());
     let topline = format!("Program {} with {}", if success {"successful"} else {"failed"}, exit_code_str);
 
-    MessageDetailBuilder::with_raw(raw)
+    output.new_builder(raw)
         .text(vec![FormattedString::plain(topline)])
         .section("Stderr", |section| {
             section.append_styled(output.get_stderr(), Style::Monospace);
@@ -96,5 +179,4 @@ fn to_detail_verbose(output: &ProgramOutput) -> MessageDetail {
         .section("Stdout", |section| {
             section.append_styled(output.get_stdout(), Style::Monospace);
         })
-        .build()
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+const BACKSPACE: char = '\x08';
+
+/// Removes terminal control sequences from program output, so that it is readable in a notification:
+/// - ANSI escape sequences (colours, cursor movement etc.) are removed.
+/// - Carriage returns overwrite the start of the line, as they would in a terminal,
+///   so progress bars collapse to their final state.
+/// - Backspaces delete the previous character.
+/// - Other control characters, except tabs, are removed.
+pub fn sanitize(s: &str) -> String {
+    let stripped = strip_escape_sequences(s);
+    stripped.split('\n')
+        .map(collapse_line)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn strip_escape_sequences(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Control Sequence Introducer, e.g. ESC[1;31m, ends with a byte in the range @ to ~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Operating System Command, e.g. setting the window title, ends with BEL or ESC\
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Other escape sequences are a single character.
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Applies carriage returns and backspaces within a single line.
+fn collapse_line(line: &str) -> String {
+    let mut buffer: Vec<char> = vec![];
+    let mut cursor: usize = 0;
+    for c in line.chars() {
+        match c {
+            '\r' => cursor = 0,
+            BACKSPACE => cursor = cursor.saturating_sub(1),
+            '\t' => write_char(&mut buffer, &mut cursor, c),
+            c if c.is_control() => {}
+            c => write_char(&mut buffer, &mut cursor, c),
+        }
+    }
+    buffer.into_iter().collect()
+}
+
+fn write_char(buffer: &mut Vec<char>, cursor: &mut usize, c: char) {
+    if *cursor < buffer.len() {
+        buffer[*cursor] = c;
+    } else {
+        buffer.push(c);
+    }
+    *cursor += 1;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_colours() {
+        let s = "\x1b[1;31mERROR\x1b[0m: disk full\n\x1b]0;title\x07done";
+        assert_eq!("ERROR: disk full\ndone", sanitize(s));
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        let s = "Downloading\n[##   ] 40%\r[#### ] 80%\r[#####] 100%\nFinished\r\n";
+        assert_eq!("Downloading\n[#####] 100%\nFinished\n", sanitize(s));
+    }
+
+    #[test]
+    fn test_backspace() {
+        assert_eq!("abd", sanitize("abc\x08d"));
+    }
+}
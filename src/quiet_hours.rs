@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::PathBuf;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime, TimeZone};
+use log::warn;
+use rnotifylib::message::author::Author;
+use rnotifylib::message::{Level, Message};
+use serde::{Serialize, Deserialize};
+use crate::digest::{self, DigestEntry};
+use crate::notification::{severity, Notification};
+
+/// A period of each day in which notifications below a level are deferred until the period ends.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct QuietHours {
+    start: NaiveTime,
+    /// May be before start, in which case quiet hours continue over midnight.
+    end: NaiveTime,
+    /// Notifications at this level or above are sent immediately.
+    #[serde(default = "default_min_level")]
+    min_level: Level,
+}
+
+fn default_min_level() -> Level {
+    Level::Error
+}
+
+impl QuietHours {
+    pub fn new(start: NaiveTime, end: NaiveTime, min_level: Level) -> Self {
+        Self {
+            start,
+            end,
+            min_level,
+        }
+    }
+
+    /// If the notification should be deferred, gives the unix time to defer it until.
+    pub fn defer_until(&self, notification: &Notification, now: &DateTime<Local>) -> Option<u64> {
+        if severity(notification.get_level()) >= severity(&self.min_level) {
+            return None;
+        }
+        self.get_end(now)
+    }
+
+    /// If now is within quiet hours, gives the unix time they end.
+    pub fn get_end(&self, now: &DateTime<Local>) -> Option<u64> {
+        let time = now.time();
+        let end_date = if self.start < self.end {
+            if time < self.start || time >= self.end {
+                return None;
+            }
+            now.date_naive()
+        } else if self.start > self.end {
+            if time >= self.start {
+                now.date_naive() + Duration::days(1)
+            } else if time < self.end {
+                now.date_naive()
+            } else {
+                return None;
+            }
+        } else {
+            return None;
+        };
+        let end = Local.from_local_datetime(&NaiveDateTime::new(end_date, self.end)).earliest()
+            // The end time doesn't exist on this day due to daylight savings, so end an hour later.
+            .unwrap_or_else(|| *now + Duration::hours(1));
+        Some(end.timestamp() as u64)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct DeferredEntry {
+    until: u64,
+    #[serde(flatten)]
+    entry: DigestEntry,
+    /// The deferred message, which isn't known for notifications deferred by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<Message>,
+}
+
+/// Notifications deferred by quiet hours.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Deferred {
+    #[serde(default)]
+    entries: Vec<DeferredEntry>,
+}
+
+impl Deferred {
+    pub fn add(&mut self, notification: &Notification, routes: Vec<String>, until: u64, now: &DateTime<Local>) {
+        self.entries.push(DeferredEntry {
+            until,
+            entry: DigestEntry::new(notification, routes, now),
+            message: Some(notification.get_message().clone()),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The unix time that the next deferred notifications are due.
+    pub fn next_due(&self) -> Option<u64> {
+        self.entries.iter().map(|e| e.until).min()
+    }
+
+    /// Removes the notifications whose quiet hours have ended, giving their messages to send, with the route to send each through.
+    /// Those whose message isn't known are combined into one message listing them.
+    pub fn take_due(&mut self, now: u64, author: &Author) -> Vec<(String, Message)> {
+        let (due, deferred): (Vec<DeferredEntry>, Vec<DeferredEntry>) = std::mem::take(&mut self.entries).into_iter()
+            .partition(|e| e.until <= now);
+        self.entries = deferred;
+        let mut messages = vec![];
+        let mut unknown = vec![];
+        for deferred in due {
+            match deferred.message {
+                Some(message) => {
+                    messages.extend(deferred.entry.get_routes().iter().map(|route| (route.clone(), message.clone())));
+                }
+                None => unknown.push(deferred.entry),
+            }
+        }
+        messages.extend(digest::create_combined_messages("Deferred during quiet hours", &unknown, author));
+        messages
+    }
+
+    pub fn read_from_string(s: &str) -> Result<Deferred, String> {
+        serde_yaml::from_str(s).map_err(|err| format!("Error parsing deferred notifications: {err}"))
+    }
+
+    pub fn write_to_string(&self) -> String {
+        serde_yaml::to_string(self).expect("Failed to serialize deferred notifications")
+    }
+}
+
+pub fn read_deferred(path: &PathBuf) -> Deferred {
+    if !path.exists() {
+        return Deferred::default();
+    }
+    let deferred_str = fs::read_to_string(path).expect("Failed to read deferred notifications");
+    match Deferred::read_from_string(&deferred_str) {
+        Ok(deferred) => deferred,
+        Err(err) => {
+            warn!("{err}, starting with no deferred notifications.");
+            Deferred::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rnotifylib::message::MessageDetail;
+    use crate::config::JobDefinitionId;
+    use crate::job_state::ResultKind;
+    use crate::notification;
+    use super::*;
+
+    #[test]
+    fn test_overnight() {
+        let quiet_hours = QuietHours::new(NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                                          NaiveTime::from_hms_opt(7, 0, 0).unwrap(), Level::Error);
+        let at = |d, h| Local.with_ymd_and_hms(2022, 12, d, h, 30, 0).unwrap();
+        let seven_am = |d| Local.with_ymd_and_hms(2022, 12, d, 7, 0, 0).unwrap().timestamp() as u64;
+
+        assert_eq!(Some(seven_am(7)), quiet_hours.get_end(&at(6, 23)));
+        assert_eq!(Some(seven_am(6)), quiet_hours.get_end(&at(6, 3)));
+        assert_eq!(None, quiet_hours.get_end(&at(6, 7)));
+        assert_eq!(None, quiet_hours.get_end(&at(6, 12)));
+    }
+
+    #[test]
+    fn test_take_due() {
+        let id = JobDefinitionId::try_new("backup-nightly".to_owned()).unwrap();
+        let message = Message::new(Level::Warn, Some("Backup slow".to_owned()), MessageDetail::Raw("took 3h".to_owned()),
+                                   None, notification::daemon_author(), 0);
+        let notification = Notification::new(id, ResultKind::Warning, Level::Warn, "Backup slow".to_owned(), message);
+        let mut deferred = Deferred::default();
+        deferred.add(&notification, vec!["chat".to_owned()], 200, &Local::now());
+
+        let mut deferred = Deferred::read_from_string(&deferred.write_to_string()).unwrap();
+        assert!(deferred.take_due(199, &notification::daemon_author()).is_empty());
+        let due = deferred.take_due(200, &notification::daemon_author());
+        assert_eq!(1, due.len());
+        assert_eq!("chat", due[0].0);
+        assert_eq!(&Some("Backup slow".to_owned()), due[0].1.get_title());
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn test_daytime() {
+        let quiet_hours = QuietHours::new(NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+                                          NaiveTime::from_hms_opt(13, 0, 0).unwrap(), Level::Warn);
+        let at = |h, m| Local.with_ymd_and_hms(2022, 12, 6, h, m, 0).unwrap();
+        assert!(quiet_hours.get_end(&at(12, 30)).is_some());
+        assert!(quiet_hours.get_end(&at(13, 0)).is_none());
+        assert!(quiet_hours.get_end(&at(11, 59)).is_none());
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt::{Debug, Formatter};
+use log::warn;
+use regex::Regex;
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+
+const REPLACEMENT: &str = "[REDACTED]";
+
+/// Rules for removing secrets from job output, before it is sent anywhere or logged.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct RedactionRules {
+    /// Regular expressions, any match of which is redacted.
+    #[serde(default)]
+    patterns: Vec<RedactPattern>,
+    /// Names of environment variables whose values are secret, and so are redacted wherever they appear.
+    #[serde(default)]
+    secret_env: Vec<String>,
+}
+
+/// A compiled regular expression, which is validated when the config is loaded.
+#[derive(Clone)]
+pub struct RedactPattern(Regex);
+
+impl PartialEq for RedactPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Debug for RedactPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0.as_str())
+    }
+}
+
+impl Serialize for RedactPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        self.0.as_str().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RedactPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let string = String::deserialize(deserializer)?;
+        Regex::new(&string)
+            .map(RedactPattern)
+            .map_err(|err| serde::de::Error::custom(format!("Invalid redaction pattern '{string}': {err}")))
+    }
+}
+
+/// Applies a set of [RedactionRules] to text.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    literals: Vec<String>,
+}
+
+impl Redactor {
+    /// Creates a redactor from multiple sets of rules, e.g. the global and per job rules.
+    /// The values of secret environment variables are read now.
+    pub fn new(rules: &[&RedactionRules]) -> Self {
+        let mut patterns = vec![];
+        let mut literals = vec![];
+        for rule in rules {
+            patterns.extend(rule.patterns.iter().map(|p| p.0.clone()));
+            for name in &rule.secret_env {
+                match std::env::var(name) {
+                    Ok(value) if !value.is_empty() => literals.push(value),
+                    _ => warn!("Secret environment variable {name} is not set, so cannot be redacted."),
+                }
+            }
+        }
+        // Redact longer values first, in case one secret contains another.
+        literals.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        Self {
+            patterns,
+            literals,
+        }
+    }
+
+    pub fn redact(&self, s: &str) -> String {
+        let mut result = s.to_owned();
+        for literal in &self.literals {
+            result = result.replace(literal.as_str(), REPLACEMENT);
+        }
+        for pattern in &self.patterns {
+            result = pattern.replace_all(&result, REPLACEMENT).into_owned();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        std::env::set_var("RNOTIFYD_TEST_SECRET", "hunter2");
+        let rules: RedactionRules = serde_yaml::from_str("patterns: ['token=\\w+']\nsecret_env: [RNOTIFYD_TEST_SECRET]").unwrap();
+        let redactor = Redactor::new(&[&rules]);
+
+        assert_eq!("login with [REDACTED] and [REDACTED]", redactor.redact("login with hunter2 and token=abc123"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let rules: Result<RedactionRules, _> = serde_yaml::from_str("patterns: ['(unclosed']");
+        assert!(rules.is_err());
+    }
+}
@@ -1,16 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use log::error;
 use rnotifydlib::config::JobDefinitionId;
 use crate::next_run::ProvisionalJobRuns;
 
 pub struct RunningJobs {
     map: HashMap<JobDefinitionId, Vec<u64>>,
+    /// Runs which have been warned about taking too long, by job id and start time.
+    warned: HashSet<(JobDefinitionId, u64)>,
+    /// Runs which have been notified about skipping scheduled runs, by job id and start time.
+    overrun_notified: HashSet<(JobDefinitionId, u64)>,
 }
 
 impl RunningJobs {
     pub fn new() -> Self {
         Self {
             map: HashMap::new(),
+            warned: HashSet::new(),
+            overrun_notified: HashSet::new(),
         }
     }
 
@@ -26,6 +32,18 @@ impl RunningJobs {
                 vec.retain(|i| *i != started);
             }
         }
+        self.warned.remove(&(id.clone(), started));
+        self.overrun_notified.remove(&(id.clone(), started));
+    }
+
+    /// Records that the run has been warned about taking too long, giving false if it already had been.
+    pub fn mark_warned(&mut self, id: &JobDefinitionId, started: u64) -> bool {
+        self.warned.insert((id.clone(), started))
+    }
+
+    /// Records that the run has been notified about skipping scheduled runs, giving false if it already had been.
+    pub fn mark_overrun_notified(&mut self, id: &JobDefinitionId, started: u64) -> bool {
+        self.overrun_notified.insert((id.clone(), started))
     }
 
     pub fn any_running(&self, id: &JobDefinitionId) -> bool {
@@ -34,6 +52,13 @@ impl RunningJobs {
             .is_some()
     }
 
+    /// Gets the start time of the longest running run of the job.
+    pub fn get_earliest(&self, id: &JobDefinitionId) -> Option<u64> {
+        self.map.get(id)
+            .and_then(|vec| vec.iter().min())
+            .copied()
+    }
+
     pub fn get_running(&self) -> &HashMap<JobDefinitionId, Vec<u64>> {
         &self.map
     }
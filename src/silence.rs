@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local};
+use rnotifylib::message::author::Author;
+use rnotifylib::message::message_detail_builder::MessageDetailBuilder;
+use rnotifylib::message::{Level, Message};
+use serde::{Serialize, Deserialize};
+use crate::config::JobDefinitionId;
+use crate::frequency::{FixedPeriodInner, Frequency};
+use crate::notification::Notification;
+
+/// Which jobs a silence or maintenance window applies to.
+/// If neither a job nor a tag is given, it applies to all jobs.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct SilenceTarget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    job: Option<JobDefinitionId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+}
+
+impl SilenceTarget {
+    pub fn new(job: Option<JobDefinitionId>, tag: Option<String>) -> Self {
+        Self {
+            job,
+            tag,
+        }
+    }
+
+    pub fn matches(&self, id: &JobDefinitionId, tags: &[String]) -> bool {
+        self.job.iter().all(|job| job == id) && self.tag.iter().all(|tag| tags.contains(tag))
+    }
+
+    pub fn describe(&self) -> String {
+        match (&self.job, &self.tag) {
+            (None, None) => String::from("all jobs"),
+            (Some(job), None) => format!("job {job}"),
+            (None, Some(tag)) => format!("tag {tag}"),
+            (Some(job), Some(tag)) => format!("job {job} with tag {tag}"),
+        }
+    }
+}
+
+/// Suppresses notifications for some jobs until a given time.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Silence {
+    #[serde(flatten)]
+    target: SilenceTarget,
+    until: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    suppressed: Vec<SuppressedNotification>,
+}
+
+impl Silence {
+    pub fn new(target: SilenceTarget, until: DateTime<Local>, reason: Option<String>) -> Self {
+        Self {
+            target,
+            until,
+            reason,
+            suppressed: vec![],
+        }
+    }
+
+    pub fn get_target(&self) -> &SilenceTarget {
+        &self.target
+    }
+
+    pub fn get_until(&self) -> &DateTime<Local> {
+        &self.until
+    }
+
+    pub fn get_reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn get_suppressed(&self) -> &Vec<SuppressedNotification> {
+        &self.suppressed
+    }
+
+    /// Whether this is the same silence as the other, ignoring what it has suppressed.
+    fn same_as(&self, other: &Silence) -> bool {
+        self.target == other.target && self.until == other.until && self.reason == other.reason
+    }
+
+    fn describe(&self) -> String {
+        match &self.reason {
+            None => format!("Silence of {}", self.target.describe()),
+            Some(reason) => format!("Silence of {} ({reason})", self.target.describe()),
+        }
+    }
+}
+
+/// A recurring period in which notifications for some jobs are suppressed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MaintenanceWindow {
+    name: String,
+    /// When the window starts. FixedPeriod is not supported, as it has no fixed start time.
+    frequency: Frequency,
+    duration: FixedPeriodInner,
+    #[serde(flatten)]
+    target: SilenceTarget,
+}
+
+impl MaintenanceWindow {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_active(&self, now: &DateTime<Local>) -> bool {
+        if let Frequency::FixedPeriod(_) = self.frequency {
+            return false;
+        }
+        let window_start = *now - chrono::Duration::seconds(self.duration.as_seconds() as i64);
+        let start = self.frequency.next(&window_start, None);
+        start >= window_start.timestamp() as u64 && start <= now.timestamp() as u64
+    }
+}
+
+/// A notification that was not sent because of a silence or maintenance window.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SuppressedNotification {
+    job: JobDefinitionId,
+    title: String,
+    level: Level,
+    time: DateTime<Local>,
+}
+
+/// A silence or maintenance window that has ended, with the notifications it suppressed.
+pub struct EndedSilence {
+    description: String,
+    suppressed: Vec<SuppressedNotification>,
+}
+
+impl EndedSilence {
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    /// Creates a message summarising the suppressed notifications, or None if nothing was suppressed.
+    pub fn create_summary_message(&self, author: &Author) -> Option<Message> {
+        if self.suppressed.is_empty() {
+            return None;
+        }
+        let title = format!("{} ended, {} notifications were suppressed", self.description, self.suppressed.len());
+        let lines: Vec<String> = self.suppressed.iter()
+            .map(|s| format!("- {} {} [{:?}]: {}", s.time.format("%Y-%m-%d %H:%M"), s.job, s.level, s.title))
+            .collect();
+        let detail = MessageDetailBuilder::with_raw(lines.join("\n"))
+            .section("Suppressed notifications", |s| {
+                s.append_plain(lines.join("\n"));
+            })
+            .build();
+        let level = match self.suppressed.iter().all(|s| s.level == Level::Info) {
+            true => Level::Info,
+            false => Level::Warn,
+        };
+        Some(Message::new(level, Some(title), detail, None,
+                          author.clone(),
+                          Local::now().timestamp_millis()))
+    }
+}
+
+/// The silences that are in effect, and what they and any active maintenance windows have suppressed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Silences {
+    #[serde(default)]
+    silences: Vec<Silence>,
+    /// Notifications suppressed by maintenance windows, keyed by window name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    window_suppressed: BTreeMap<String, Vec<SuppressedNotification>>,
+}
+
+impl Silences {
+    pub fn get_silences(&self) -> &Vec<Silence> {
+        &self.silences
+    }
+
+    pub fn add(&mut self, silence: Silence) {
+        self.silences.push(silence);
+    }
+
+    /// Removes the silences with exactly the given target, giving them as ended.
+    pub fn remove(&mut self, target: &SilenceTarget) -> Vec<EndedSilence> {
+        let (removed, kept) = std::mem::take(&mut self.silences).into_iter()
+            .partition(|silence| &silence.target == target);
+        self.silences = kept;
+        removed.into_iter().map(Self::ended).collect()
+    }
+
+    /// If the notification is silenced, records it as suppressed and returns true.
+    pub fn suppress_if_silenced(&mut self, notification: &Notification, tags: &[String],
+                                windows: &[MaintenanceWindow], now: &DateTime<Local>) -> bool {
+        let id = notification.get_job_id();
+        let suppressed = SuppressedNotification {
+            job: id.clone(),
+            title: notification.get_title().to_owned(),
+            level: notification.get_level().clone(),
+            time: *now,
+        };
+
+        if let Some(silence) = self.silences.iter_mut()
+            .find(|silence| &silence.until > now && silence.target.matches(id, tags)) {
+            silence.suppressed.push(suppressed);
+            return true;
+        }
+        if let Some(window) = windows.iter()
+            .find(|window| window.target.matches(id, tags) && window.is_active(now)) {
+            self.window_suppressed.entry(window.name.clone()).or_default().push(suppressed);
+            return true;
+        }
+        false
+    }
+
+    /// Removes silences which have expired, and forgets maintenance windows which are no longer active.
+    pub fn take_ended(&mut self, windows: &[MaintenanceWindow], now: &DateTime<Local>) -> Vec<EndedSilence> {
+        let (ended, active): (Vec<Silence>, Vec<Silence>) = std::mem::take(&mut self.silences).into_iter()
+            .partition(|silence| &silence.until <= now);
+        self.silences = active;
+        let mut result: Vec<EndedSilence> = ended.into_iter().map(Self::ended).collect();
+
+        let ended_windows: Vec<String> = self.window_suppressed.keys()
+            .filter(|name| !windows.iter().any(|w| &w.name == *name && w.is_active(now)))
+            .cloned()
+            .collect();
+        for name in ended_windows {
+            if let Some(suppressed) = self.window_suppressed.remove(&name) {
+                result.push(EndedSilence {
+                    description: format!("Maintenance window {name}"),
+                    suppressed,
+                });
+            }
+        }
+        result
+    }
+
+    /// Replaces the silences with those that have been edited externally,
+    /// giving the silences that no longer exist as ended.
+    /// What maintenance windows suppressed is kept from whichever has recorded more of it.
+    pub fn replace_silences(&mut self, new: Silences) -> Vec<EndedSilence> {
+        for (name, suppressed) in new.window_suppressed {
+            let current = self.window_suppressed.entry(name).or_default();
+            if suppressed.len() > current.len() {
+                *current = suppressed;
+            }
+        }
+
+        let (kept, removed): (Vec<Silence>, Vec<Silence>) = std::mem::take(&mut self.silences).into_iter()
+            .partition(|old| new.silences.iter().any(|s| s.same_as(old)));
+        self.silences = new.silences.into_iter()
+            .map(|mut silence| {
+                if let Some(old) = kept.iter().find(|old| old.same_as(&silence)) {
+                    if silence.suppressed.len() < old.suppressed.len() {
+                        silence.suppressed = old.suppressed.clone();
+                    }
+                }
+                silence
+            })
+            .collect();
+        removed.into_iter().map(Self::ended).collect()
+    }
+
+    fn ended(silence: Silence) -> EndedSilence {
+        EndedSilence {
+            description: silence.describe(),
+            suppressed: silence.suppressed,
+        }
+    }
+
+    pub fn read_from_string(s: &str) -> Result<Silences, String> {
+        serde_yaml::from_str(s).map_err(|err| format!("Error parsing silences: {err}"))
+    }
+
+    pub fn write_to_string(&self) -> String {
+        serde_yaml::to_string(self).expect("Failed to serialize silences")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use super::*;
+
+    #[test]
+    fn test_maintenance_window() {
+        let window: MaintenanceWindow = serde_yaml::from_str(
+            "name: nightly\nfrequency:\n  type: Daily\n  time: \"02:00:00\"\nduration:\n  hours: 2\n  minutes: 0\n  seconds: 0\ntag: db").unwrap();
+
+        let at = |h, m| Local.with_ymd_and_hms(2022, 12, 6, h, m, 0).unwrap();
+        assert!(!window.is_active(&at(1, 59)));
+        assert!(window.is_active(&at(2, 0)));
+        assert!(window.is_active(&at(3, 30)));
+        assert!(!window.is_active(&at(4, 1)));
+
+        let id = JobDefinitionId::try_new("backup-nightly".to_owned()).unwrap();
+        assert!(window.target.matches(&id, &["db".to_owned()]));
+        assert!(!window.target.matches(&id, &[]));
+    }
+
+    #[test]
+    fn test_silence_target() {
+        let id = JobDefinitionId::try_new("backup-nightly".to_owned()).unwrap();
+        let other = JobDefinitionId::try_new("ping".to_owned()).unwrap();
+        let target = SilenceTarget::new(Some(id.clone()), None);
+        assert!(target.matches(&id, &[]));
+        assert!(!target.matches(&other, &[]));
+        assert!(SilenceTarget::default().matches(&other, &[]));
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+use log::{error, info, warn};
+use rnotifydlib::silence::{EndedSilence, Silences};
+
+/// The silences, kept in sync with the silences file.
+/// The file can be edited while rnotifyd is running to add or remove silences.
+pub struct SilenceStore {
+    path: PathBuf,
+    silences: Silences,
+    modified: Option<SystemTime>,
+}
+
+impl SilenceStore {
+    pub fn load(path: PathBuf) -> Self {
+        let mut store = Self {
+            path,
+            silences: Silences::default(),
+            modified: None,
+        };
+        store.reload_if_changed();
+        store
+    }
+
+    pub fn get(&self) -> &Silences {
+        &self.silences
+    }
+
+    pub fn get_mut(&mut self) -> &mut Silences {
+        &mut self.silences
+    }
+
+    /// Re-reads the silences file if it has been modified since it was last read or written,
+    /// giving the silences that were removed from it.
+    pub fn reload_if_changed(&mut self) -> Vec<EndedSilence> {
+        let modified = match std::fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return vec![], // No silences file yet.
+        };
+        if self.modified == Some(modified) {
+            return vec![];
+        }
+        self.modified = Some(modified);
+
+        let silences = std::fs::read_to_string(&self.path)
+            .map_err(|err| format!("Error reading silences file: {err}"))
+            .and_then(|s| Silences::read_from_string(&s));
+        match silences {
+            Ok(silences) => {
+                info!("Loaded silences from {:?}", self.path);
+                self.silences.replace_silences(silences)
+            }
+            Err(err) => {
+                warn!("{err}, keeping the current silences.");
+                vec![]
+            }
+        }
+    }
+
+    pub fn save(&mut self) {
+        if let Err(err) = std::fs::write(&self.path, self.silences.write_to_string()) {
+            error!("Error writing silences: {err}");
+            return;
+        }
+        // Don't reload our own changes.
+        self.modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeZone};
+    use rnotifylib::message::{Level, Message, MessageDetail};
+    use rnotifydlib::config::JobDefinitionId;
+    use rnotifydlib::job_state::ResultKind;
+    use rnotifydlib::notification::{daemon_author, Notification};
+    use rnotifydlib::silence::MaintenanceWindow;
+    use super::*;
+
+    #[test]
+    fn test_window_suppressed_survives_reload() {
+        let path = std::env::temp_dir().join(format!("rnotifyd-silences-test-{}.yaml", std::process::id()));
+        let windows: Vec<MaintenanceWindow> = serde_yaml::from_str(
+            "- name: nightly\n  frequency:\n    type: Daily\n    time: \"02:00:00\"\n  duration:\n    hours: 2\n    minutes: 0\n    seconds: 0").unwrap();
+        let during = Local.with_ymd_and_hms(2022, 12, 6, 2, 30, 0).unwrap();
+        let after = Local.with_ymd_and_hms(2022, 12, 6, 4, 30, 0).unwrap();
+
+        let id = JobDefinitionId::try_new("backup-nightly".to_owned()).unwrap();
+        let message = Message::new(Level::Error, Some("Backup failed".to_owned()), MessageDetail::Raw("exit 1".to_owned()),
+                                   None, daemon_author(), 0);
+        let notification = Notification::new(id, ResultKind::Failed, Level::Error, "Backup failed".to_owned(), message);
+
+        let mut store = SilenceStore::load(path.clone());
+        assert!(store.get_mut().suppress_if_silenced(&notification, &[], &windows, &during));
+        store.save();
+
+        // As if rnotifyd was restarted.
+        let mut store = SilenceStore::load(path.clone());
+        std::fs::remove_file(&path).unwrap();
+        let ended = store.get_mut().take_ended(&windows, &after);
+        assert_eq!(1, ended.len());
+        assert!(ended[0].create_summary_message(&daemon_author()).is_some());
+    }
+}
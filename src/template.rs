@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::str::FromStr;
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+
+/// A string which may contain variables, such as "{{job_id}} failed on {{hostname}}".
+/// Templates are validated when they are parsed, so unknown variables are rejected when the config is loaded.
+#[derive(Clone)]
+pub struct Template {
+    source: String,
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Variable(TemplateVariable),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TemplateVariable {
+    JobId,
+    Hostname,
+    ExitCode,
+    Duration,
+    Attempt,
+    Result,
+    StdoutFirstLine,
+}
+
+impl TemplateVariable {
+    pub fn name(&self) -> &str {
+        match self {
+            TemplateVariable::JobId => "job_id",
+            TemplateVariable::Hostname => "hostname",
+            TemplateVariable::ExitCode => "exit_code",
+            TemplateVariable::Duration => "duration",
+            TemplateVariable::Attempt => "attempt",
+            TemplateVariable::Result => "result",
+            TemplateVariable::StdoutFirstLine => "stdout_first_line",
+        }
+    }
+}
+
+impl FromStr for TemplateVariable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "job_id" => Ok(TemplateVariable::JobId),
+            "hostname" => Ok(TemplateVariable::Hostname),
+            "exit_code" => Ok(TemplateVariable::ExitCode),
+            "duration" => Ok(TemplateVariable::Duration),
+            "attempt" => Ok(TemplateVariable::Attempt),
+            "result" => Ok(TemplateVariable::Result),
+            "stdout_first_line" => Ok(TemplateVariable::StdoutFirstLine),
+            _ => Err(format!("Unknown template variable '{s}'")),
+        }
+    }
+}
+
+/// The values of the variables available to a template.
+/// Variables without a value are rendered as "n/a".
+#[derive(Default, Debug)]
+pub struct TemplateContext {
+    values: HashMap<TemplateVariable, String>,
+}
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set<S: ToString>(&mut self, variable: TemplateVariable, value: S) {
+        self.values.insert(variable, value.to_string());
+    }
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start != 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_owned()));
+            }
+            let after_start = &rest[start + 2..];
+            let end = after_start.find("}}")
+                .ok_or_else(|| format!("Unclosed '{{{{' in template '{s}'"))?;
+            let variable = after_start[..end].trim().parse()?;
+            parts.push(TemplatePart::Variable(variable));
+            rest = &after_start[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_owned()));
+        }
+        Ok(Self {
+            source: s.to_owned(),
+            parts,
+        })
+    }
+
+    /// Gets the variables used by the template.
+    pub fn get_variables(&self) -> Vec<TemplateVariable> {
+        self.parts.iter()
+            .filter_map(|part| match part {
+                TemplatePart::Variable(variable) => Some(*variable),
+                TemplatePart::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let mut result = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(s) => result.push_str(s),
+                TemplatePart::Variable(variable) => {
+                    result.push_str(context.values.get(variable).map(|s| s.as_str()).unwrap_or("n/a"))
+                }
+            }
+        }
+        result
+    }
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Debug for Template {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        self.source.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let string = String::deserialize(deserializer)?;
+        Template::parse(&string).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse("{{ job_id }} {{result}} on {{hostname}} after {{duration}}").unwrap();
+        let mut context = TemplateContext::new();
+        context.set(TemplateVariable::JobId, "backup-nightly");
+        context.set(TemplateVariable::Result, "failed");
+        context.set(TemplateVariable::Hostname, "db-02");
+
+        assert_eq!("backup-nightly failed on db-02 after n/a", template.render(&context));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Template::parse("{{job}} failed").is_err());
+        assert!(Template::parse("{{job_id failed").is_err());
+        assert!(Template::parse("No variables").is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+use crate::config::JobDefinitionId;
+use crate::job_state::ResultKind;
+
+/// The number of triggered runs that are remembered, so their result can be polled.
+const MAX_RUNS: usize = 100;
+/// The number of runs of each job that can be waiting to start, so that repeated triggers don't pile up.
+const MAX_QUEUED_PER_JOB: usize = 5;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    /// Waiting to start, because the job doesn't allow parallel runs and is already running.
+    Queued,
+    Running,
+    Finished,
+    /// Never started, because the job was removed from the config.
+    Cancelled,
+}
+
+/// A run of a job which was triggered on demand, rather than by its schedule.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TriggeredRun {
+    id: u64,
+    job: JobDefinitionId,
+    status: RunStatus,
+    queued: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    started: Option<u64>,
+    /// The result of the run, such as "ok" or "failed", once it has finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u64>,
+}
+
+impl TriggeredRun {
+    pub fn get_status(&self) -> RunStatus {
+        self.status
+    }
+}
+
+/// The most recently triggered runs, oldest first.
+#[derive(Debug, Default)]
+pub struct TriggeredRuns {
+    next_id: u64,
+    runs: VecDeque<TriggeredRun>,
+}
+
+impl TriggeredRuns {
+    /// Queues a run of the job, giving its id, or an error if too many runs of the job are already queued.
+    pub fn queue(&mut self, job: JobDefinitionId, now: u64) -> Result<u64, String> {
+        let queued = self.runs.iter().filter(|run| run.job == job && run.status == RunStatus::Queued).count();
+        if queued >= MAX_QUEUED_PER_JOB {
+            return Err(format!("{job} already has {queued} runs waiting to start"));
+        }
+        self.next_id += 1;
+        let id = self.next_id;
+        self.runs.push_back(TriggeredRun {
+            id,
+            job,
+            status: RunStatus::Queued,
+            queued: now,
+            started: None,
+            result: None,
+            duration: None,
+        });
+        if self.runs.len() > MAX_RUNS {
+            if let Some(index) = self.runs.iter().position(|run| matches!(run.status, RunStatus::Finished | RunStatus::Cancelled)) {
+                self.runs.remove(index);
+            }
+        }
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&TriggeredRun> {
+        self.runs.iter().find(|run| run.id == id)
+    }
+
+    /// Gets the runs waiting to start, oldest first.
+    pub fn get_queued(&self) -> Vec<(u64, JobDefinitionId)> {
+        self.runs.iter()
+            .filter(|run| run.status == RunStatus::Queued)
+            .map(|run| (run.id, run.job.clone()))
+            .collect()
+    }
+
+    pub fn start(&mut self, id: u64, now: u64) {
+        if let Some(run) = self.get_mut(id) {
+            run.status = RunStatus::Running;
+            run.started = Some(now);
+        }
+    }
+
+    pub fn finish(&mut self, id: u64, result: ResultKind, duration: Option<u64>) -> Option<&TriggeredRun> {
+        let run = self.get_mut(id)?;
+        run.status = RunStatus::Finished;
+        run.result = Some(result.type_str().to_owned());
+        run.duration = duration;
+        Some(run)
+    }
+
+    /// Cancels the run if it hasn't started yet.
+    pub fn cancel(&mut self, id: u64) -> Option<&TriggeredRun> {
+        let run = self.get_mut(id).filter(|run| run.status == RunStatus::Queued)?;
+        run.status = RunStatus::Cancelled;
+        Some(run)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut TriggeredRun> {
+        self.runs.iter_mut().find(|run| run.id == id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_triggered_runs() {
+        let job = JobDefinitionId::try_new("smoke-check".to_owned()).unwrap();
+        let mut runs = TriggeredRuns::default();
+        let first = runs.queue(job.clone(), 100).unwrap();
+        let second = runs.queue(job.clone(), 101).unwrap();
+        assert_eq!(vec![(first, job.clone()), (second, job.clone())], runs.get_queued());
+
+        runs.start(first, 102);
+        assert_eq!(vec![(second, job.clone())], runs.get_queued());
+        let finished = runs.finish(first, ResultKind::Failed, Some(3)).unwrap();
+        assert_eq!(RunStatus::Finished, finished.get_status());
+        assert_eq!(r#"{"id":1,"job":"smoke-check","status":"finished","queued":100,"started":102,"result":"failed","duration":3}"#,
+                   serde_json::to_string(finished).unwrap());
+    }
+
+    #[test]
+    fn test_max_queued() {
+        let job = JobDefinitionId::try_new("smoke-check".to_owned()).unwrap();
+        let other = JobDefinitionId::try_new("backup".to_owned()).unwrap();
+        let mut runs = TriggeredRuns::default();
+        let ids: Vec<u64> = (0..MAX_QUEUED_PER_JOB).map(|_| runs.queue(job.clone(), 100).unwrap()).collect();
+        assert!(runs.queue(job.clone(), 100).is_err());
+        assert!(runs.queue(other, 100).is_ok());
+
+        // Once one starts, another can be queued.
+        runs.start(ids[0], 101);
+        assert!(runs.queue(job, 101).is_ok());
+    }
+}